@@ -1,10 +1,16 @@
-use clap::Parser;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser, ValueEnum};
+use serde::Serialize;
 
 /// A cargo subcommand for profiling binaries using samply
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone, Serialize)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
-    /// Trailing arguments passed to the binary being profiled
+    /// Trailing arguments passed to the binary being profiled, put them after `--` if they look
+    /// like options
     #[arg(name = "TRAILING_ARGUMENTS")]
     pub args: Vec<String>,
 
@@ -12,27 +18,335 @@ pub struct Config {
     #[arg(short, long, default_value = "samply")]
     pub profile: String,
 
-    /// Binary to run
+    /// Remove the selected profile from `Cargo.toml` after the run if this run added it
+    #[arg(long, conflicts_with = "print_build_command")]
+    pub revert_profile_inject: bool,
+
+    /// Set `debug = true` in the selected profile if it lacks debug info
+    #[arg(long)]
+    pub repair_profile: bool,
+
+    /// Profile the selected profile inherits from when it has to be added to `Cargo.toml`
+    #[arg(long, value_name = "PROFILE", default_value = "release")]
+    pub profile_inherits: String,
+
+    /// Opt-level of the selected profile when it has to be added to `Cargo.toml`
+    #[arg(long, value_name = "LEVEL", value_parser = ["0", "1", "2", "3", "s", "z"])]
+    pub profile_opt_level: Option<String>,
+
+    /// Set the opt-level of a package in the selected profile, as `<spec>=<opt-level>`, can be
+    /// repeated
+    #[arg(long, value_name = "SPEC=LEVEL")]
+    pub profile_package: Vec<String>,
+
+    /// Build with the release profile, shorthand for `--profile release`
+    #[arg(short = 'r', long)]
+    pub release: bool,
+
+    /// Binary to run, `*` and `?` globs run every matching binary
     #[arg(short, long)]
     pub bin: Option<String>,
 
-    /// Example to run
+    /// Example to run, `*` and `?` globs run every matching example
     #[arg(short, long)]
     pub example: Option<String>,
 
-    /// Build features to enable
+    /// Integration test to run, `*` and `?` globs run every matching test
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["bin", "example"])]
+    pub test: Option<String>,
+
+    /// Test name filter passed to the test harness before the trailing arguments
+    #[arg(long, value_name = "PATTERN", requires = "test")]
+    pub test_filter: Option<String>,
+
+    /// Prebuilt executable to run instead of building a target
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["bin", "example", "test", "name", "bins", "examples", "workspace", "package"]
+    )]
+    pub bin_path: Option<PathBuf>,
+
+    /// Ask which binary or example to run when that can't be determined
     #[arg(short, long)]
-    pub features: Option<String>,
+    pub interactive: bool,
+
+    /// Target name to run for every kind it exists as, used with `--all-kinds`
+    #[arg(long, requires = "all_kinds", conflicts_with_all = ["bin", "example", "test"])]
+    pub name: Option<String>,
+
+    /// Profile each binary, example, bench and integration test named `--name`
+    #[arg(long, requires = "name")]
+    pub all_kinds: bool,
+
+    /// Profile every binary of the package in turn
+    #[arg(long, conflicts_with_all = ["bin", "example", "test", "name", "examples"])]
+    pub bins: bool,
+
+    /// Profile every example of the package in turn
+    #[arg(long, conflicts_with_all = ["bin", "example", "test", "name"])]
+    pub examples: bool,
+
+    /// Profile the binary of every workspace member
+    #[arg(long, conflicts_with_all = ["bin", "example", "test", "name", "bins", "examples"])]
+    pub workspace: bool,
+
+    /// Workspace member to take the targets from
+    #[arg(long, value_name = "SPEC", conflicts_with = "workspace")]
+    pub package: Option<String>,
+
+    /// Build features to enable, space or comma separated, can be repeated
+    #[arg(short, long)]
+    pub features: Vec<String>,
 
     /// Disable default features
     #[arg(long)]
     pub no_default_features: bool,
 
-    /// Print extra output to help debug problems
-    #[arg(short, long, default_value_t = false)]
-    pub verbose: bool,
+    /// CPU to build for, added to the rustflags in effect as `-C target-cpu=<CPU>`
+    #[arg(long, value_name = "CPU")]
+    pub target_cpu: Option<String>,
+
+    /// Turn incremental compilation on or off for the build, cargo decides if not given;
+    /// turning it off can make the profile more faithful, since incremental builds are
+    /// optimized less thoroughly
+    #[arg(long, value_name = "BOOL")]
+    pub incremental: Option<bool>,
 
-    /// Disable the automatic samply start
+    /// Number of parallel jobs cargo builds with
+    #[arg(short, long, value_name = "N", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub jobs: Option<usize>,
+
+    /// Have cargo build as much as possible even if a crate fails to compile
+    #[arg(long)]
+    pub keep_going: bool,
+
+    /// Print the cargo build command and how long the build took
+    #[arg(long)]
+    pub trace_cargo: bool,
+
+    /// Have cargo write its build timing report
+    #[arg(long)]
+    pub cargo_timings: bool,
+
+    /// Cargo executable to build with, takes precedence over the `CARGO` variable
+    #[arg(long, value_name = "PATH")]
+    pub cargo_path: Option<PathBuf>,
+
+    /// Run cargo without accessing the network
+    #[arg(long)]
+    pub offline: bool,
+
+    /// Times to try `cargo metadata` before giving up, for flaky network filesystems
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub metadata_retries: u32,
+
+    /// Only print errors, also from cargo and the compiler
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print extra output to help debug problems, `-vv` and `-vvv` also make cargo verbose
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Disable the automatic samply start. Under samply, stderr reaches the terminal through a
+    /// pipe that cargo-samply reads samply's errors from, so the program sees no terminal there
     #[arg(short, long, default_value_t = false)]
     pub no_samply: bool,
+
+    /// Extra arguments passed to `samply record`, split like a shell would
+    #[arg(long, allow_hyphen_values = true)]
+    pub samply_args: Option<String>,
+
+    /// Single extra argument passed to `samply record` as is, after `--samply-args`, can be
+    /// repeated
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    pub samply_arg: Vec<String>,
+
+    /// Port samply serves the recorded profile on
+    #[arg(long)]
+    pub port: Option<u16>,
+
+    /// Only record and save the profile, then print where it was saved instead of opening it
+    #[arg(long, conflicts_with_all = ["no_samply", "backend"])]
+    pub symbolicate_only: bool,
+
+    /// Save the recorded profile as plain JSON instead of gzipped JSON
+    #[arg(long)]
+    pub no_compress: bool,
+
+    /// Directory samply saves the profiles to, each named after the time and the target
+    #[arg(long, value_name = "DIR")]
+    pub profile_output_dir: Option<PathBuf>,
+
+    /// Profiler to record with
+    #[arg(long, value_enum, default_value_t = Backend::Samply)]
+    pub backend: Backend,
+
+    /// File `perf record` writes to with `--backend perf`
+    #[arg(long, value_name = "FILE", default_value = "perf.data")]
+    pub perf_output: String,
+
+    /// Program to run the binary with, like cargo's target runner, followed by its own arguments
+    /// split like a shell would, e.g. `--runner "qemu-aarch64 -L /sysroot"`
+    #[arg(long, value_name = "PROG")]
+    pub runner: Option<String>,
+
+    /// File the profiled binary reads as its stdin, `-` for the stdin of `cargo samply`
+    #[arg(long, value_name = "FILE")]
+    pub stdin: Option<PathBuf>,
+
+    /// Shell command to run after the build and before the binary starts, can be repeated
+    #[arg(long, value_name = "CMD")]
+    pub before_run: Vec<String>,
+
+    /// Shell command to run once the binary exited, can be repeated; `CARGO_SAMPLY_PROFILE_PATH`
+    /// holds the recording path
+    #[arg(long, value_name = "CMD")]
+    pub after_run: Vec<String>,
+
+    /// Skip the `--after-run` hooks if the run failed
+    #[arg(long, requires = "after_run")]
+    pub after_run_on_success_only: bool,
+
+    /// Run with an empty environment; samply and perf, which pass theirs on to the binary, keep
+    /// the variables they need, such as `PATH` and `HOME`
+    #[arg(long)]
+    pub env_clear: bool,
+
+    /// Keep this variable with `--env-clear`, can be repeated
+    #[arg(long, value_name = "KEY", requires = "env_clear")]
+    pub env_passthrough: Vec<String>,
+
+    /// Dotenv file with `KEY=VALUE` lines to set in the environment of the profiled binary
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<PathBuf>,
+
+    /// Variable to set for the profiled binary, as `KEY=VALUE`, overrides `--env-file`, can be
+    /// repeated
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub env: Vec<String>,
+
+    /// Don't check the built binary for debug info
+    #[arg(long)]
+    pub no_debug_info_check: bool,
+
+    /// Print the build and run commands instead of running them
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the build command and exit, adding a missing profile so that it can be run
+    #[arg(long, conflicts_with_all = ["bin_path", "dry_run"])]
+    pub print_build_command: bool,
+
+    /// Build, then print the path of the built artifact instead of running it
+    #[arg(long)]
+    pub print_artifact_path: bool,
+
+    /// Print the options resolved from the command line and the manifest metadata as JSON and
+    /// exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Remove the `samply` profile from `Cargo.toml` and exit
+    #[arg(long)]
+    pub clean: bool,
+
+    /// Open the profile the last recording in this workspace saved with `samply load` and exit
+    #[arg(long)]
+    pub open_last: bool,
+
+    /// Format of our own log messages
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// When to color our messages and cargo's
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    /// List the targets of the package and exit
+    #[arg(long)]
+    pub list_targets: bool,
+
+    /// Only list targets of this kind, can be repeated
+    #[arg(long, value_enum, requires = "list_targets")]
+    pub kind: Vec<TargetKind>,
+
+    /// Output format for `--list-targets` and the summary after a run, `--summary json` prints
+    /// the summary of each run as a JSON line
+    #[arg(long, visible_alias = "summary", value_enum, default_value_t = MessageFormat::Human)]
+    pub message_format: MessageFormat,
+
+    /// Interrupt the profiled process and samply after the given number of seconds, as Ctrl+C
+    /// would; they run in a process group of their own then, so the process can't read from the
+    /// terminal
+    #[arg(long, value_name = "SECS")]
+    pub duration: Option<u64>,
+
+    /// Run the binary this many times in a row, each recording in its own session saved under a
+    /// numbered name, e.g. `profile-2.json.gz`; stops at the first failed run
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub repeat: u32,
+
+    /// Keep repeating after a failed run
+    #[arg(long, requires = "repeat")]
+    pub repeat_continue: bool,
+
+    /// Never interrupt samply, even with `--duration`, and wait until it exits on its own
+    #[arg(long)]
+    pub wait: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Samply,
+    Perf,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// Color if stderr is a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether our messages on stderr are colored.
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageFormat {
+    Human,
+    Json,
+    /// Bare target names, one per line, only for `--list-targets`
+    Names,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    Bin,
+    Example,
+    Bench,
+    Test,
 }
@@ -1,5 +1,7 @@
+use std::ffi::OsString;
 use std::io;
 use std::path::PathBuf;
+use std::process::ExitStatus;
 use std::result;
 use std::str::Utf8Error;
 use thiserror::Error;
@@ -18,16 +20,81 @@ pub enum Error {
     TomlDeserialization(#[from] toml::de::Error),
     #[error(transparent)]
     TomlManifest(#[from] cargo_toml::Error),
+    #[error(transparent)]
+    TomlEdit(#[from] toml_edit::TomlError),
+    #[error("Invalid `--profile-package` '{0}', expected `<spec>=<opt-level>` with an opt-level of 0-3, s or z")]
+    InvalidProfilePackage(String),
+    #[error("Invalid `--env` '{0}', expected `KEY=VALUE`")]
+    InvalidEnv(String),
+    #[error("{}:{line}: {reason}", path.display())]
+    InvalidEnvFile {
+        path: PathBuf,
+        line: usize,
+        reason: &'static str,
+    },
+    #[error("Conflicting profile options: {detail}")]
+    ProfileConflict { detail: String },
+    #[error("`--profile-inherits` names '{0}', which is neither a built-in profile nor defined in 'Cargo.toml'")]
+    UnknownBaseProfile(String),
+    #[error("'{0}' in 'Cargo.toml' is not a table")]
+    InvalidProfileTable(String),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    CargoMetadata(#[from] cargo_metadata::Error),
+    #[error("Invalid samply arguments: {0}")]
+    SamplyArgs(#[from] shell_words::ParseError),
+    #[error("Invalid `--runner` '{0}': {1}")]
+    InvalidRunner(String, shell_words::ParseError),
     #[error("--bin and --example are mutually exclusive")]
     BinAndExampleMutuallyExclusive,
+    #[error("The `--before-run` hook `{command}` failed with {status}")]
+    HookFailed { command: String, status: ExitStatus },
     #[error("Build failed")]
     CargoBuildFailed,
+    #[error("cargo did not report the executable it built for '{0}'")]
+    ArtifactNotReported(String),
     #[error("No binary found in 'Cargo.toml'")]
     NoBinaryFound,
     #[error("The binary to run can't be determined. Use the `--bin` option to specify a binary, or the `default-run` manifest key.")]
     BinaryToRunNotDetermined,
-    #[error("Failed to locate project")]
-    CargoLocateProjectFailed,
+    #[error("`default-run` names '{0}', which is not a binary of the package")]
+    DefaultRunNotFound(String),
+    #[error("Every {0} requires features that are not enabled")]
+    AllTargetsNeedFeatures(&'static str),
+    #[error("No binary, example, bench or test named '{0}' found")]
+    NoTargetNamed(String),
+    #[error("No target matches '{pattern}', available: {available}")]
+    NoTargetMatches { pattern: String, available: String },
+    #[error("No workspace member named '{name}'{suggestion}, available: {available}")]
+    NoPackageNamed {
+        name: String,
+        /// Points out the closest member name, if there is a close one.
+        suggestion: String,
+        available: String,
+    },
+    #[error("The workspace has no root package, select a member with `--package`: {0}")]
+    VirtualManifest(String),
+    #[error("samply was not found at {0:?}, install it with `cargo install --locked samply`")]
+    SamplyNotFound(OsString),
+    #[error("`{option}` needs samply's `{samply_option}`, which {samply} lacks; update it with `cargo install --locked samply`")]
+    SamplyTooOld {
+        option: &'static str,
+        samply_option: &'static str,
+        samply: String,
+    },
+    #[error("No recording was saved in this workspace yet, run `cargo samply` first")]
+    NoLastProfile,
+    #[error("samply could not start its server: {message}; pass `--port N` to use another port")]
+    SamplyServerFailed { message: String },
+    #[error("The samply port is given both by `--port` and in the samply arguments")]
+    PortGivenTwice,
+    #[error(
+        "The profile output is given both by `--profile-output-dir` and in the samply arguments"
+    )]
+    OutputGivenTwice,
+    #[error("Failed to locate project, there is no 'Cargo.toml' in '{}' or its parents", .0.display())]
+    CargoLocateProjectFailed(PathBuf),
 }
 
 /// Alias for a `Result` with the error type `hld::Error`.
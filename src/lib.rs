@@ -0,0 +1,19 @@
+//! Build a cargo target with a profile that keeps debug info and record it with samply.
+//!
+//! The `cargo samply` command is a thin layer over [`generate_plan`] and [`execute_plan`],
+//! [`Profiler`] gives access to the same steps from Rust code.
+
+#[macro_use]
+extern crate log;
+
+pub mod cli;
+pub mod error;
+pub mod logger;
+mod metadata;
+mod plan;
+mod profiler;
+mod util;
+
+pub use plan::{execute_plan, generate_plan, open_last_profile, ExecutionPlan};
+pub use profiler::Profiler;
+pub use util::{locate_project, package_manifest, remove_profile, Target, Targets};
@@ -0,0 +1,91 @@
+//! The loggers for `--log-format text` and `--log-format json`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, Log, Metadata, Record};
+
+/// Prints records to stderr, prefixed by their level except for info, and by their origin at the
+/// trace level.
+struct TextLogger {
+    level: Level,
+    color: bool,
+}
+
+impl Log for TextLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let level = record.level().as_str().to_lowercase();
+        let header = match (self.level, record.level()) {
+            (Level::Trace, _) => format!(
+                "{}({}): {}: ",
+                record.module_path().unwrap_or("?"),
+                record.line().map_or("?".to_string(), |l| l.to_string()),
+                level
+            ),
+            (_, Level::Info) => String::new(),
+            _ => format!("{}: ", level),
+        };
+        let color = match record.level() {
+            Level::Error => "31",
+            Level::Warn => "33",
+            Level::Info => "",
+            Level::Debug => "34",
+            Level::Trace => "35",
+        };
+        if self.color && !header.is_empty() && !color.is_empty() {
+            eprintln!("\x1b[{}m{}\x1b[0m{}", color, header, record.args());
+        } else {
+            eprintln!("{}{}", header, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the text logger, showing records up to `level`, with colored prefixes if `color`.
+pub fn init_text(level: Level, color: bool) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(TextLogger { level, color }))?;
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
+
+struct JsonLogger {
+    level: Level,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        let line = serde_json::json!({
+            "level": record.level().as_str().to_lowercase(),
+            "message": record.args().to_string(),
+            "timestamp": timestamp,
+        });
+        eprintln!("{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the JSON logger, showing records up to `level`.
+pub fn init_json(level: Level) -> Result<(), log::SetLoggerError> {
+    log::set_boxed_logger(Box::new(JsonLogger { level }))?;
+    log::set_max_level(level.to_level_filter());
+    Ok(())
+}
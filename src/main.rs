@@ -1,16 +1,12 @@
 #[macro_use]
 extern crate log;
 
-mod cli;
-mod error;
-mod util;
-
-use std::process::Command;
-use std::vec;
-
-use clap::Parser;
-
-use crate::util::{ensure_samply_profile, guess_bin, locate_project, CommandExt};
+use cargo_samply::cli::{self, LogFormat, MessageFormat};
+use cargo_samply::{
+    error, execute_plan, generate_plan, locate_project, logger, open_last_profile,
+    package_manifest, remove_profile, Targets,
+};
+use clap::{error::ErrorKind, parser::ValueSource, CommandFactory, FromArgMatches};
 
 fn main() {
     if let Err(err) = run() {
@@ -20,71 +16,112 @@ fn main() {
 }
 
 fn run() -> error::Result<()> {
-    let cli = cli::Config::parse();
-    ocli::init(if cli.verbose {
-        log::Level::Debug
-    } else {
-        log::Level::Info
-    })?;
-
-    if cli.bin.is_some() && cli.example.is_some() {
-        return Err(error::Error::BinAndExampleMutuallyExclusive);
+    let command = cli::Config::command();
+    // `run` takes the same options, it only turns the recording off
+    let run_command = command
+        .clone()
+        .name("run")
+        .about("Build and run the binary without recording it, like `--no-samply`");
+    let open_last_command = clap::Command::new("open-last")
+        .about("Open the profile the last recording in this workspace saved, like `--open-last`");
+    let with_subcommands = |command: clap::Command| {
+        command
+            .subcommand(run_command.clone())
+            .subcommand(open_last_command.clone())
+            .args_conflicts_with_subcommands(true)
+            .disable_help_subcommand(true)
+    };
+    // cargo runs `cargo samply <args>` as `cargo-samply samply <args>`, a leading `samply` is
+    // only taken as a trailing argument after `--`
+    let cargo_command = with_subcommands(command.clone().name("samply").hide(true));
+    let mut matches = with_subcommands(command)
+        .subcommand(cargo_command)
+        .get_matches();
+    if let Some(samply_matches) = matches.subcommand_matches("samply") {
+        matches = samply_matches.clone();
     }
-
-    // check if cargo.toml exists
-    // check project path using locate-project
-    let cargo_toml = locate_project()?;
-    debug!("cargo.toml: {:?}", cargo_toml);
-
-    // check if profile exists
-    // if not add profile
-    // if yes print warning
-    if cli.profile == "samply" {
-        ensure_samply_profile(&cargo_toml)?;
+    let open_last = matches.subcommand_matches("open-last").is_some();
+    let (matches, run_only) = match matches.subcommand_matches("run") {
+        Some(run_matches) => (run_matches.clone(), true),
+        None => (matches, false),
+    };
+    let mut cli = cli::Config::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    cli.no_samply |= run_only;
+    cli.open_last |= open_last;
+    if cli.message_format == MessageFormat::Names && !cli.list_targets {
+        cli::Config::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "`--message-format names` can only be used with `--list-targets`",
+            )
+            .exit();
     }
-
-    let (bin_opt, bin_name) = if let Some(bin) = cli.bin {
-        ("--bin", bin)
-    } else if let Some(example) = cli.example {
-        ("--example", example)
-    } else {
-        ("--bin", guess_bin(&cargo_toml)?)
+    let level = match cli.verbose {
+        0 if cli.quiet => log::Level::Error,
+        0 => log::Level::Info,
+        1 => log::Level::Debug,
+        _ => log::Level::Trace,
     };
-
-    let mut args = vec!["build", "--profile", &cli.profile, &bin_opt, &bin_name];
-    if let Some(features) = cli.features.as_ref() {
-        args.push("--features");
-        args.push(features);
+    match cli.log_format {
+        LogFormat::Text => logger::init_text(level, cli.color.enabled())?,
+        LogFormat::Json => logger::init_json(level)?,
     }
-    if cli.no_default_features {
-        args.push("--no-default-features");
+
+    if let Some(cargo_path) = cli.cargo_path.as_ref() {
+        // cargo sets `CARGO` for subcommands, so the flag has to replace it
+        std::env::set_var("CARGO", cargo_path);
     }
-    let exit_code = Command::new("cargo").args(args).call()?;
-    if !exit_code.success() {
-        return Err(error::Error::CargoBuildFailed);
+
+    if cli.clean {
+        let cargo_toml = locate_project()?;
+        debug!("cargo.toml: {:?}", cargo_toml);
+        return remove_profile(&cargo_toml, "samply");
     }
 
-    // run samply on the binary
-    // if it fails print error
-    let root = cargo_toml.parent().unwrap();
-    let bin_path = if bin_opt == "--bin" {
-        root.join("target").join(&cli.profile).join(&bin_name)
-    } else {
-        root.join("target")
-            .join(&cli.profile)
-            .join("examples")
-            .join(&bin_name)
-    };
+    if cli.open_last {
+        let cargo_toml = locate_project()?;
+        debug!("cargo.toml: {:?}", cargo_toml);
+        let status = open_last_profile(&cargo_toml)?;
+        exit_on_failure(status);
+        return Ok(());
+    }
 
-    if !cli.no_samply {
-        Command::new("samply")
-            .arg("record")
-            .arg(bin_path)
-            .args(cli.args)
-            .call()?;
-    } else {
-        Command::new(bin_path).args(cli.args).call()?;
+    if cli.list_targets {
+        let cargo_toml = locate_project()?;
+        debug!("cargo.toml: {:?}", cargo_toml);
+        let (_, manifest) = package_manifest(&cargo_toml, cli.package.as_deref(), (&cli).into())?;
+        let mut targets = Targets::from_manifest(&manifest);
+        if !cli.kind.is_empty() {
+            targets.retain_kinds(&cli.kind);
+        }
+        match cli.message_format {
+            MessageFormat::Human => targets.print(),
+            MessageFormat::Json => println!("{}", serde_json::to_string(&targets)?),
+            MessageFormat::Names => targets.print_names(),
+        }
+        return Ok(());
     }
 
+    let explicit_profile = matches.value_source("profile") == Some(ValueSource::CommandLine);
+    let plan = generate_plan(cli, explicit_profile)?;
+    if plan.config.print_config {
+        println!("{}", serde_json::to_string(&plan.config)?);
+        return Ok(());
+    }
+    let status = execute_plan(&plan)?;
+    exit_on_failure(status);
     Ok(())
 }
+
+/// Exits with the code of a failed run, so that scripts see it. A run killed by a signal exits
+/// with 128 plus the signal number, like a shell reports it.
+fn exit_on_failure(status: std::process::ExitStatus) {
+    if status.success() {
+        return;
+    }
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        std::process::exit(128 + signal);
+    }
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,53 @@
+use std::{fs, path::Path, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    cli::Config,
+    error::{self, IOResultExt},
+};
+
+/// Defaults read from `[workspace.metadata.samply]` and `[package.metadata.samply]`.
+#[derive(Debug, Default, Deserialize)]
+pub struct ManifestConfig {
+    #[serde(alias = "default_profile")]
+    pub profile: Option<String>,
+    pub samply_args: Option<String>,
+}
+
+impl ManifestConfig {
+    /// Reads the configuration, package values taking precedence over workspace values.
+    pub fn from_manifest(cargo_toml: &Path) -> error::Result<Self> {
+        let content = fs::read_to_string(cargo_toml).path_ctx(cargo_toml)?;
+        let manifest = toml::Table::from_str(&content)?;
+        let table = |section: &str| -> error::Result<ManifestConfig> {
+            match manifest
+                .get(section)
+                .and_then(|s| s.get("metadata"))
+                .and_then(|m| m.get("samply"))
+            {
+                Some(samply) => Ok(samply.clone().try_into()?),
+                None => Ok(ManifestConfig::default()),
+            }
+        };
+        let workspace = table("workspace")?;
+        let package = table("package")?;
+        Ok(ManifestConfig {
+            profile: package.profile.or(workspace.profile),
+            samply_args: package.samply_args.or(workspace.samply_args),
+        })
+    }
+
+    /// Fills in the options that were not given on the command line.
+    pub fn apply(self, cli: &mut Config, explicit_profile: bool) {
+        if let Some(profile) = self.profile {
+            if !cli.release && !explicit_profile {
+                debug!("using profile '{}' from the manifest", profile);
+                cli.profile = profile;
+            }
+        }
+        if cli.samply_args.is_none() {
+            cli.samply_args = self.samply_args;
+        }
+    }
+}
@@ -0,0 +1,1023 @@
+use std::borrow::Cow;
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::vec;
+
+use serde::Serialize;
+
+use crate::cli::{Backend, ColorChoice, Config, MessageFormat};
+use crate::error::{self, IOResultExt};
+use crate::metadata::ManifestConfig;
+use crate::util::{
+    artifact_has_debug_info, build_target, cargo_config_env, cargo_program, cargo_version,
+    configured_rustflags, ensure_profile, expand_target_pattern, features_fingerprint,
+    features_fingerprint_changed, find_targets_named, fingerprint_path, guess_bin, host_triple,
+    ignore_interrupts, last_profile_path, locate_project, normalize_features, package_manifest,
+    parse_env, parse_profile_package, perf_program, plan_profile, profile_dir,
+    profile_has_debug_info, profile_strip, prompt_target, read_env_file, remove_profile,
+    repair_profile, required_features, samply_failure_hint, samply_port_in_use, samply_program,
+    samply_record_knows, samply_version, set_profile_packages, unknown_features, workspace_targets,
+    write_features_fingerprint, CommandExt, Target,
+};
+
+/// The targets `cargo samply` is going to build and run, together with the resolved options.
+#[derive(Debug, Clone)]
+pub struct ExecutionPlan {
+    pub config: Config,
+    /// Empty with `--bin-path`, which needs no project.
+    pub cargo_toml: PathBuf,
+    /// Empty with `--bin-path`, which is run as is.
+    pub targets: Vec<Target>,
+    /// Problems that don't prevent the run but likely spoil the profile.
+    pub warnings: Vec<String>,
+}
+
+/// Resolves the options and targets for a run.
+///
+/// Options missing from `config` are taken from the manifest metadata, the profile only if
+/// `explicit_profile` is `false`. Nothing is written, [`execute_plan`] adds the profile to
+/// `Cargo.toml` if it is missing.
+pub fn generate_plan(mut config: Config, explicit_profile: bool) -> error::Result<ExecutionPlan> {
+    if config.bin.is_some() && config.example.is_some() {
+        return Err(error::Error::BinAndExampleMutuallyExclusive);
+    }
+    if config.release {
+        if explicit_profile && config.profile != "release" {
+            return Err(error::Error::ProfileConflict {
+                detail: format!(
+                    "`--release` selects the release profile, but `--profile {}` was given too",
+                    config.profile
+                ),
+            });
+        }
+        config.profile = "release".to_string();
+    }
+    if config.profile == "debug" {
+        return Err(error::Error::ProfileConflict {
+            detail: "cargo reserves the profile name 'debug', use `--profile dev` for the \
+                     debug build"
+                .to_string(),
+        });
+    }
+    config.features = normalize_features(&config.features);
+    if let Some(stdin) = stdin_file(&config) {
+        fs::metadata(stdin).path_ctx(stdin)?;
+    }
+    // report malformed variables before building, they are read again for every run
+    if let Some(env_file) = &config.env_file {
+        read_env_file(env_file)?;
+    }
+    for value in &config.env {
+        parse_env(value)?;
+    }
+    runner(&config)?;
+    if config.bin_path.is_some() {
+        check_profile_output_dir(&config)?;
+        return Ok(ExecutionPlan {
+            config,
+            cargo_toml: PathBuf::new(),
+            targets: vec![],
+            warnings: vec![],
+        });
+    }
+
+    // check if cargo.toml exists
+    // check project path using locate-project
+    let cargo_toml = locate_project()?;
+    debug!("cargo.toml: {:?}", cargo_toml);
+    ManifestConfig::from_manifest(&cargo_toml)?.apply(&mut config, explicit_profile);
+    // fail before building if the samply arguments are unusable
+    check_profile_output_dir(&config)?;
+
+    // fail before building if an override is malformed, `execute_plan` applies them
+    for value in &config.profile_package {
+        parse_profile_package(value)?;
+    }
+
+    let mut warnings = vec![];
+    let content = fs::read_to_string(&cargo_toml).path_ctx(&cargo_toml)?;
+    let mut raw_manifest = toml::Table::from_str(&content)?;
+    // judge the profile as `execute_plan` is going to leave it
+    plan_profile(
+        &mut raw_manifest,
+        &config.profile,
+        &config.profile_inherits,
+        config.repair_profile,
+    );
+    if config.profile != "samply" && !profile_has_debug_info(&raw_manifest, &config.profile) {
+        warnings.push(format!(
+            "profile '{0}' has no debug info, the recording will lack symbols; \
+             use `--profile samply` or set `debug = true` in `[profile.{0}]`",
+            config.profile
+        ));
+        // already warned, the binary won't have any either
+        config.no_debug_info_check = true;
+    }
+    if let Some(strip) = profile_strip(&raw_manifest, &config.profile) {
+        warnings.push(format!(
+            "profile '{0}' strips its binaries (`strip = {1}`), the recording will lack symbols; \
+             set `strip = false` in `[profile.{0}]`",
+            config.profile, strip
+        ));
+        config.no_debug_info_check = true;
+    }
+
+    if config.target_cpu.as_deref() == Some("native") {
+        warnings.push(
+            "`--target-cpu native` builds for this machine's CPU, the profile may not carry \
+             over to other machines"
+                .to_string(),
+        );
+    }
+    if config.keep_going {
+        // stable since cargo 1.74
+        if let Some(version) = cargo_version().filter(|v| *v < (1, 74)) {
+            warnings.push(format!(
+                "cargo {}.{} does not support `--keep-going`, building without it",
+                version.0, version.1
+            ));
+            config.keep_going = false;
+        }
+    }
+
+    let targets = if config.workspace {
+        let targets = workspace_targets(&cargo_toml, (&config).into())?;
+        if targets.is_empty() {
+            return Err(error::Error::NoBinaryFound);
+        }
+        targets
+    } else {
+        let (package, manifest) =
+            package_manifest(&cargo_toml, config.package.as_deref(), (&config).into())?;
+        warnings.extend(unknown_features(&manifest, &config.features));
+        let mut targets = if let Some(name) = config.name.as_ref() {
+            find_targets_named(&manifest, name)?
+        } else if let Some(bin) = config.bin.as_ref() {
+            expand_target_pattern(&manifest, "--bin", bin)?
+        } else if let Some(example) = config.example.as_ref() {
+            expand_target_pattern(&manifest, "--example", example)?
+        } else if let Some(test) = config.test.as_ref() {
+            expand_target_pattern(&manifest, "--test", test)?
+        } else if config.bins || config.examples {
+            let bin_opt = if config.bins { "--bin" } else { "--example" };
+            let mut targets = expand_target_pattern(&manifest, bin_opt, "*")?;
+            targets.retain(|target| {
+                let missing: Vec<String> = required_features(&manifest, target)
+                    .into_iter()
+                    .filter(|f| !config.features.contains(f))
+                    .collect();
+                if !missing.is_empty() {
+                    warnings.push(format!(
+                        "skipping {} '{}', it requires the features: {}",
+                        bin_opt.trim_start_matches('-'),
+                        target.name,
+                        missing.join(", ")
+                    ));
+                }
+                missing.is_empty()
+            });
+            if targets.is_empty() {
+                return Err(error::Error::AllTargetsNeedFeatures(
+                    bin_opt.trim_start_matches('-'),
+                ));
+            }
+            targets
+        } else {
+            let target = match guess_bin(&manifest) {
+                Err(error::Error::BinaryToRunNotDetermined) if config.interactive => {
+                    prompt_target(&manifest)?.ok_or(error::Error::BinaryToRunNotDetermined)?
+                }
+                bin => Target::new("--bin", bin?),
+            };
+            for feature in required_features(&manifest, &target) {
+                if !config.features.contains(&feature) {
+                    debug!(
+                        "enabling feature '{}' required by '{}'",
+                        feature, target.name
+                    );
+                    config.features.push(feature);
+                }
+            }
+            vec![target]
+        };
+        for target in &mut targets {
+            target.package = package.clone();
+        }
+        targets
+    };
+
+    Ok(ExecutionPlan {
+        config,
+        cargo_toml,
+        targets,
+        warnings,
+    })
+}
+
+/// Builds and runs every target of the plan in turn.
+///
+/// Returns the exit status of the first run that failed, or of the last run.
+pub fn execute_plan(plan: &ExecutionPlan) -> error::Result<ExitStatus> {
+    for warning in &plan.warnings {
+        warn!("{}", warning);
+    }
+    let cli = &plan.config;
+    let runs_samply = !cli.no_samply && cli.backend == Backend::Samply;
+    if runs_samply && !cli.dry_run && !cli.print_build_command && !cli.print_artifact_path {
+        check_samply_options(cli)?;
+    }
+    if let Some(dir) = cli.profile_output_dir.as_ref().filter(|_| !cli.dry_run) {
+        fs::create_dir_all(dir).path_ctx(dir)?;
+    }
+    if let Some(bin_path) = plan.config.bin_path.as_ref() {
+        let name = bin_path.file_stem().unwrap_or_default().to_string_lossy();
+        let cli = &*with_output_file(cli, &name);
+        if cli.dry_run {
+            for hook in &cli.before_run {
+                println!("{}", hook_command(cli, hook)?.display());
+            }
+            println!("{}", run_command(cli, bin_path)?.display());
+            return Ok(ExitStatus::default());
+        }
+        return run_repeatedly(cli, bin_path, &name, |_, _, _| Ok(()));
+    }
+    let mut revert = None;
+    if !cli.dry_run {
+        let added_profile = ensure_profile(
+            &plan.cargo_toml,
+            &cli.profile,
+            &cli.profile_inherits,
+            cli.profile_opt_level.as_deref(),
+        )?;
+        // also after a failed build, the profile is of no use without the run
+        revert = ProfileRevert::new(cli, &plan.cargo_toml, added_profile);
+        if cli.repair_profile {
+            repair_profile(&plan.cargo_toml, &cli.profile)?;
+        }
+        let profile_packages = cli
+            .profile_package
+            .iter()
+            .map(|value| parse_profile_package(value))
+            .collect::<error::Result<Vec<_>>>()?;
+        if !profile_packages.is_empty() {
+            set_profile_packages(&plan.cargo_toml, &cli.profile, &profile_packages)?;
+        }
+    }
+    // Ctrl+C during the build then only stops cargo, so that the profile still gets reverted
+    let _interrupts = revert.is_some().then(ignore_interrupts);
+    let root = plan.cargo_toml.parent().unwrap();
+    profile_targets(&plan.config, root, &plan.targets)
+}
+
+/// Removes the profile this run added to `Cargo.toml` when dropped, for
+/// `--revert-profile-inject`, so that no early return leaves it behind.
+struct ProfileRevert {
+    cargo_toml: PathBuf,
+    profile: String,
+}
+
+impl ProfileRevert {
+    fn new(cli: &Config, cargo_toml: &Path, added_profile: bool) -> Option<Self> {
+        (added_profile && cli.revert_profile_inject).then(|| ProfileRevert {
+            cargo_toml: cargo_toml.to_path_buf(),
+            profile: cli.profile.clone(),
+        })
+    }
+}
+
+impl Drop for ProfileRevert {
+    fn drop(&mut self) {
+        if let Err(err) = remove_profile(&self.cargo_toml, &self.profile) {
+            warn!(
+                "the '{}' profile could not be removed from 'Cargo.toml': {}",
+                self.profile, err
+            );
+        }
+    }
+}
+
+/// Profiles every target in turn, returning the status of the first failed run, if any.
+fn profile_targets(cli: &Config, root: &Path, targets: &[Target]) -> error::Result<ExitStatus> {
+    let mut result: Option<ExitStatus> = None;
+    for target in targets {
+        let status = profile_target(cli, root, target)?;
+        if result.is_none_or(|s| s.success()) {
+            result = Some(status);
+        }
+    }
+    result.ok_or(error::Error::NoBinaryFound)
+}
+
+/// Builds a single target and runs it, under samply unless disabled.
+fn profile_target(cli: &Config, root: &Path, target: &Target) -> error::Result<ExitStatus> {
+    let (bin_opt, bin_name) = (target.bin_opt, target.name.as_str());
+    let output_name = match target.package.as_deref() {
+        Some(package) => format!("{}-{}", package, bin_name),
+        None => bin_name.to_string(),
+    };
+    let mut cli = with_output_file(cli, &output_name);
+    if bin_opt == "--bench" {
+        // like `cargo bench`, which tells the harness to benchmark rather than test
+        cli.to_mut().args.push("--bench".to_string());
+    }
+    let cli = &*cli;
+    let bin_path = artifact_path(cli, root, target);
+    if cli.print_build_command {
+        println!("{}", build_command(cli, target).display());
+        return Ok(ExitStatus::default());
+    }
+    if cli.dry_run {
+        println!("{}", build_command(cli, target).display());
+        let shown_path = match &bin_path {
+            Some(bin_path) => {
+                if !bin_path.exists() {
+                    info!("'{}' has not been built yet", bin_path.display());
+                }
+                bin_path.clone()
+            }
+            None => {
+                info!(
+                    "the path of the '{}' executable is only known after the build",
+                    bin_name
+                );
+                PathBuf::from(format!("<{} executable>", bin_name))
+            }
+        };
+        for hook in &cli.before_run {
+            println!("{}", hook_command(cli, hook)?.display());
+        }
+        println!("{}", run_command(cli, &shown_path)?.display());
+        return Ok(ExitStatus::default());
+    }
+
+    // features change the binary but not its path, so cargo silently rebuilds
+    let fingerprint = features_fingerprint(&cli.features, cli.no_default_features);
+    let fingerprint_path = fingerprint_path(root, bin_opt.trim_start_matches('-'), bin_name);
+    if cli.verbose > 0 && features_fingerprint_changed(&fingerprint_path, &fingerprint) {
+        info!(
+            "the requested features differ from the last build of '{}', it will be rebuilt",
+            bin_name
+        );
+    }
+
+    let mut command = build_command(cli, target);
+    if cli.trace_cargo {
+        info!("running {}", command.display());
+    }
+    let build_start = Instant::now();
+    let (exit_code, executable) = build_target(&mut command, target, cli)?;
+    let build_time = build_start.elapsed();
+    if cli.trace_cargo {
+        info!("cargo build took {:.2}s", build_time.as_secs_f64());
+    }
+    if !exit_code.success() {
+        return Err(error::Error::CargoBuildFailed);
+    }
+    if cli.cargo_timings {
+        let report = root.join("target/cargo-timings/cargo-timing.html");
+        info!("build timings: {}", report.display());
+    }
+    write_features_fingerprint(&fingerprint_path, &fingerprint)?;
+    let bin_path = executable
+        .or(bin_path)
+        .ok_or_else(|| error::Error::ArtifactNotReported(bin_name.to_string()))?;
+
+    if !cli.no_debug_info_check && artifact_has_debug_info(&bin_path)? == Some(false) {
+        warn!(
+            "'{}' has no debug info, the recording will lack symbols; is it stripped?",
+            bin_path.display()
+        );
+    }
+    if cli.print_artifact_path {
+        println!("{}", bin_path.display());
+        return Ok(ExitStatus::default());
+    }
+
+    run_repeatedly(cli, &bin_path, bin_name, |status, output, run_time| {
+        if status.success() && !cli.no_samply && cli.backend == Backend::Samply {
+            let profile = env::current_dir()?.join(output);
+            let state = last_profile_path(root);
+            if let Some(dir) = state.parent() {
+                fs::create_dir_all(dir).path_ctx(dir)?;
+            }
+            fs::write(&state, profile.to_string_lossy().as_bytes()).path_ctx(&state)?;
+        }
+        // dashboards want the failed runs too, the log line is only for the successful ones
+        if !cli.no_samply && (status.success() || cli.message_format == MessageFormat::Json) {
+            let summary = RunSummary {
+                target: bin_name,
+                kind: bin_opt.trim_start_matches('-'),
+                package: target.package.as_deref(),
+                artifact: &bin_path,
+                profile: &cli.profile,
+                exit_code: status.code(),
+                output: (status.success() || output.exists()).then(|| output.to_path_buf()),
+                build_secs: build_time.as_secs_f64(),
+                run_secs: run_time.as_secs_f64(),
+            };
+            summary.print(cli.message_format)?;
+        }
+        Ok(())
+    })
+}
+
+/// Runs the binary `--repeat` times, numbering the recordings if there are several, and calls
+/// `after_run` with the status, recording path and duration of each run.
+///
+/// Returns the exit status of the first run that failed, or of the last run.
+fn run_repeatedly(
+    cli: &Config,
+    bin_path: &Path,
+    name: &str,
+    mut after_run: impl FnMut(ExitStatus, &Path, Duration) -> error::Result<()>,
+) -> error::Result<ExitStatus> {
+    let mut statuses = vec![];
+    for run in 1..=cli.repeat {
+        let run_start = Instant::now();
+        let status = run_target(cli, bin_path)?;
+        statuses.push(status);
+        let mut output = recording_path(cli)?;
+        if cli.repeat > 1 && !cli.no_samply && output.exists() {
+            // keep every recording, the next run would overwrite it
+            let numbered = numbered_path(&output, run);
+            fs::rename(&output, &numbered).path_ctx(&numbered)?;
+            output = numbered;
+        }
+        after_run(status, &output, run_start.elapsed())?;
+        if !status.success() && !cli.repeat_continue {
+            break;
+        }
+    }
+    if cli.repeat > 1 {
+        let codes: Vec<String> = statuses
+            .iter()
+            .map(|s| s.code().map_or_else(|| s.to_string(), |c| c.to_string()))
+            .collect();
+        info!(
+            "'{}' ran {} of {} times, exit codes: {}",
+            name,
+            statuses.len(),
+            cli.repeat,
+            codes.join(", ")
+        );
+    }
+    Ok(statuses
+        .into_iter()
+        .find(|s| !s.success())
+        .unwrap_or_default())
+}
+
+/// `path` with `-<run>` inserted before its extensions, `profile.json.gz` becomes
+/// `profile-2.json.gz`.
+fn numbered_path(path: &Path, run: u32) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let numbered = match name.split_once('.') {
+        Some((stem, extensions)) => format!("{}-{}.{}", stem, run, extensions),
+        None => format!("{}-{}", name, run),
+    };
+    path.with_file_name(numbered)
+}
+
+/// Opens the profile the last successful samply recording in the workspace of `cargo_toml` saved,
+/// with `samply load`.
+pub fn open_last_profile(cargo_toml: &Path) -> error::Result<ExitStatus> {
+    let state = last_profile_path(cargo_toml.parent().unwrap());
+    let profile = match fs::read_to_string(&state) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(error::Error::NoLastProfile)
+        }
+        result => PathBuf::from(result.path_ctx(&state)?),
+    };
+    fs::metadata(&profile).path_ctx(&profile)?;
+    match Command::new(samply_program())
+        .arg("load")
+        .arg(&profile)
+        .call()
+    {
+        Err(error::Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+            Err(error::Error::SamplyNotFound(samply_program()))
+        }
+        result => result,
+    }
+}
+
+/// The `cargo build` invocation for `target`.
+fn build_command(cli: &Config, target: &Target) -> Command {
+    let jobs = cli.jobs.map(|jobs| jobs.to_string());
+    let mut args = vec![
+        "build",
+        "--profile",
+        &cli.profile,
+        target.bin_opt,
+        &target.name,
+    ];
+    if let Some(package) = target.package.as_ref() {
+        args.push("--package");
+        args.push(package);
+    }
+    let features = cli.features.join(",");
+    if !features.is_empty() {
+        args.push("--features");
+        args.push(&features);
+    }
+    if cli.no_default_features {
+        args.push("--no-default-features");
+    }
+    if cli.offline {
+        args.push("--offline");
+    }
+    if let Some(jobs) = jobs.as_ref() {
+        args.extend(["--jobs", jobs]);
+    }
+    match cli.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => args.extend(["--color", "always"]),
+        ColorChoice::Never => args.extend(["--color", "never"]),
+    }
+    if cli.keep_going {
+        args.push("--keep-going");
+    }
+    if cli.cargo_timings {
+        args.push("--timings");
+    }
+    match cli.verbose {
+        0 | 1 => {}
+        2 => args.push("--verbose"),
+        _ => args.push("-vv"),
+    }
+    let mut command = Command::new(cargo_program());
+    command.args(args);
+    if let Some(cpu) = cli.target_cpu.as_ref() {
+        let flag = format!("target-cpu={}", cpu);
+        // cargo takes the first of these that is set, and only then `build.rustflags`
+        if let Ok(mut rustflags) = env::var("CARGO_ENCODED_RUSTFLAGS") {
+            if !rustflags.is_empty() {
+                rustflags.push('\x1f');
+            }
+            rustflags.push_str(&format!("-C\x1f{}", flag));
+            debug!("building with CARGO_ENCODED_RUSTFLAGS={:?}", rustflags);
+            command.env("CARGO_ENCODED_RUSTFLAGS", rustflags);
+        } else if let Ok(mut rustflags) = env::var("RUSTFLAGS") {
+            if !rustflags.is_empty() {
+                rustflags.push(' ');
+            }
+            rustflags.push_str(&format!("-C {}", flag));
+            debug!("building with RUSTFLAGS={:?}", rustflags);
+            command.env("RUSTFLAGS", rustflags);
+        } else {
+            // appended to the rustflags of the configuration files
+            command.arg("--config");
+            command.arg(format!("{}=[\"-C\", \"{}\"]", rustflags_key(), flag));
+        }
+    }
+    if let Some(incremental) = cli.incremental {
+        command.env("CARGO_INCREMENTAL", if incremental { "1" } else { "0" });
+    }
+    command
+}
+
+/// The configuration key `--target-cpu` is appended to. cargo ignores `build.rustflags` once
+/// a `[target]` table that applies to the build sets rustflags, and joins those of all such
+/// tables, so the flag goes to the table of the build's triple then.
+fn rustflags_key() -> String {
+    let dir = env::current_dir().unwrap_or_default();
+    // a broken configuration is left to cargo to report
+    let (tables, build) = configured_rustflags(&dir).unwrap_or_default();
+    let target_vars = env::vars_os().any(|(key, _)| {
+        key.to_str()
+            .is_some_and(|key| key.starts_with("CARGO_TARGET_") && key.ends_with("_RUSTFLAGS"))
+    });
+    if tables.is_empty() && !target_vars {
+        return "build.rustflags".to_string();
+    }
+    let Some(triple) = env::var("CARGO_BUILD_TARGET")
+        .ok()
+        .filter(|t| !t.is_empty())
+        .or_else(host_triple)
+    else {
+        return "build.rustflags".to_string();
+    };
+    let target_key = format!(
+        "target.{}.rustflags",
+        toml_edit::Key::new(&triple).display_repr()
+    );
+    let triple_var = format!(
+        "CARGO_TARGET_{}_RUSTFLAGS",
+        triple.to_uppercase().replace(['-', '.'], "_")
+    );
+    if tables.contains(&triple) || env::var_os(triple_var).is_some() {
+        return target_key;
+    }
+    // whether a `cfg(..)` table applies is not worked out here
+    let cfgs: Vec<String> = tables
+        .iter()
+        .filter(|name| name.starts_with("cfg("))
+        .map(|name| format!("`[target.{}]`", toml_edit::Key::new(name).display_repr()))
+        .collect();
+    if cfgs.is_empty() {
+        "build.rustflags".to_string()
+    } else if !build {
+        // joined with the applying tables, and nothing is lost if none applies
+        target_key
+    } else {
+        warn!(
+            "the cargo configuration sets rustflags in {} too, cargo ignores `--target-cpu` \
+             if that applies to this build",
+            cfgs.join(" and ")
+        );
+        "build.rustflags".to_string()
+    }
+}
+
+/// Where cargo puts the binary or example built for `target`, `None` for tests and benches,
+/// whose executable names carry a hash that only `build_target` learns.
+fn artifact_path(cli: &Config, root: &Path, target: &Target) -> Option<PathBuf> {
+    let mut dir = root.join("target");
+    let triple = env::var("CARGO_BUILD_TARGET")
+        .ok()
+        .filter(|t| !t.is_empty());
+    // cross builds get a directory per target triple
+    if let Some(triple) = &triple {
+        dir.push(triple);
+    }
+    let dir = dir.join(profile_dir(&cli.profile));
+    let suffix = match &triple {
+        Some(triple) if triple.contains("windows") => ".exe",
+        Some(_) => "",
+        None => env::consts::EXE_SUFFIX,
+    };
+    let file = format!("{}{}", target.name, suffix);
+    match target.bin_opt {
+        "--bin" => Some(dir.join(file)),
+        "--example" => Some(dir.join("examples").join(file)),
+        _ => None,
+    }
+}
+
+/// Runs the built binary, under the selected profiler unless disabled, between the
+/// `--before-run` and `--after-run` hooks.
+fn run_target(cli: &Config, bin_path: &Path) -> error::Result<ExitStatus> {
+    for hook in &cli.before_run {
+        let status = hook_command(cli, hook)?.call()?;
+        if !status.success() {
+            return Err(error::Error::HookFailed {
+                command: hook.clone(),
+                status,
+            });
+        }
+    }
+    let status = run_profiled(cli, bin_path)?;
+    if status.success() || !cli.after_run_on_success_only {
+        for hook in &cli.after_run {
+            let mut command = hook_command(cli, hook)?;
+            if !cli.no_samply {
+                command.env("CARGO_SAMPLY_PROFILE_PATH", recording_path(cli)?);
+            }
+            // the exit code of the profiled run matters more than the hook's
+            match command.call() {
+                Ok(hook_status) if !hook_status.success() => warn!(
+                    "the `--after-run` hook `{}` failed with {}",
+                    hook, hook_status
+                ),
+                Ok(_) => {}
+                Err(err) => warn!("the `--after-run` hook `{}` failed: {}", hook, err),
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Runs the built binary, under the selected profiler unless disabled, without the hooks.
+fn run_profiled(cli: &Config, bin_path: &Path) -> error::Result<ExitStatus> {
+    let mut command = run_command(cli, bin_path)?;
+    let _interrupts = ignore_interrupts();
+    let timeout = cli.duration.map(Duration::from_secs);
+    if cli.no_samply || cli.backend == Backend::Perf {
+        return call(&mut command, timeout);
+    }
+
+    let timeout = if cli.wait { None } else { timeout };
+    let (status, stderr) = match command.call_capturing_stderr(timeout) {
+        Err(error::Error::Io(err)) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(error::Error::SamplyNotFound(samply_program()));
+        }
+        result => result?,
+    };
+    if !status.success() {
+        if let Some(message) = samply_port_in_use(&stderr) {
+            return Err(error::Error::SamplyServerFailed {
+                message: message.to_string(),
+            });
+        }
+        if let Some(hint) = samply_failure_hint(&stderr) {
+            warn!("{}", hint);
+        }
+    } else if cli.symbolicate_only {
+        println!("{}", recording_path(cli)?.display());
+    }
+    Ok(status)
+}
+
+/// Fails if the installed samply lacks an option the flags need, going by the options its
+/// `samply record --help` lists, as the first samply versions with each of them aren't recorded
+/// anywhere we could check. samply is only asked if such a flag is used, one whose help can't be
+/// read is given the benefit of the doubt.
+fn check_samply_options(cli: &Config) -> error::Result<()> {
+    let samply_args = samply_args(cli)?;
+    let passes = |short: &str, long: &str| {
+        samply_args
+            .iter()
+            .any(|a| a == short || a == long || a.starts_with(&format!("{}=", long)))
+    };
+    // the flags of ours and in `--samply-args` that need an option older samplys lack
+    let requirements = [
+        (cli.symbolicate_only, "--symbolicate-only", "--save-only"),
+        (passes("-s", "--save-only"), "--save-only", "--save-only"),
+        (passes("-n", "--no-open"), "--no-open", "--no-open"),
+        (passes("-P", "--port"), "--port", "--port"),
+    ];
+    for (option, samply_option) in requirements
+        .into_iter()
+        .filter_map(|(used, option, samply_option)| used.then_some((option, samply_option)))
+    {
+        match samply_record_knows(samply_option) {
+            Some(false) => {
+                let samply = match samply_version() {
+                    Some((major, minor, patch)) => {
+                        format!("samply {}.{}.{}", major, minor, patch)
+                    }
+                    None => "the installed samply".to_string(),
+                };
+                return Err(error::Error::SamplyTooOld {
+                    option,
+                    samply_option,
+                    samply,
+                });
+            }
+            Some(true) => {}
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+/// The command running the binary, under the selected profiler unless disabled.
+fn run_command(cli: &Config, bin_path: &Path) -> error::Result<Command> {
+    let runner = runner(cli)?;
+    let mut command = if cli.no_samply {
+        match runner.split_first() {
+            Some((program, args)) => {
+                let mut command = Command::new(program);
+                command.args(args).arg(bin_path);
+                command
+            }
+            None => Command::new(bin_path),
+        }
+    } else if cli.backend == Backend::Perf {
+        let mut command = Command::new(perf_program());
+        command
+            .args(["record", "-g", "-o", &cli.perf_output, "--"])
+            .args(&runner)
+            .arg(bin_path);
+        command
+    } else {
+        let mut command = Command::new(samply_program());
+        command.arg("record");
+        let samply_args = samply_args(cli)?;
+        let has_output = samply_output(&samply_args).is_some();
+        command.args(samply_args);
+        if cli.no_compress && !has_output {
+            // samply gzips the profile only if the output file ends in `.gz`
+            command.args(["--output", "profile.json"]);
+        }
+        command.args(&runner).arg(bin_path);
+        command
+    };
+    command.args(&cli.test_filter);
+    command.args(&cli.args);
+    if let Some(stdin) = stdin_file(cli) {
+        command.stdin(Stdio::from(File::open(stdin).path_ctx(stdin)?));
+    }
+    apply_env(cli, &mut command)?;
+    if cli.env_clear && !cli.no_samply {
+        // the profiler runs in the environment it hands on to the binary
+        for key in PROFILER_ENV {
+            if command.get_envs().all(|(k, _)| k != key) {
+                if let Some(value) = env::var_os(key) {
+                    command.env(key, value);
+                }
+            }
+        }
+    }
+    Ok(command)
+}
+
+/// The variables samply and perf keep with `--env-clear`, which they need to find programs,
+/// their temporary files and a browser to open the profile in.
+const PROFILER_ENV: [&str; 10] = [
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XDG_RUNTIME_DIR",
+    "BROWSER",
+    "SystemRoot",
+    "USERPROFILE",
+    "TEMP",
+];
+
+/// A `--before-run` or `--after-run` hook run by the platform shell, with the environment of the
+/// profiled run.
+fn hook_command(cli: &Config, hook: &str) -> error::Result<Command> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(hook);
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(hook);
+        command
+    };
+    apply_env(cli, &mut command)?;
+    Ok(command)
+}
+
+/// The file given by `--stdin`, unless it is `-` for our own stdin.
+fn stdin_file(cli: &Config) -> Option<&Path> {
+    cli.stdin
+        .as_deref()
+        .filter(|stdin| stdin.as_os_str() != "-")
+}
+
+/// The `--runner` program and its arguments, split like a shell would.
+fn runner(cli: &Config) -> error::Result<Vec<String>> {
+    match cli.runner.as_ref() {
+        Some(runner) => shell_words::split(runner)
+            .map_err(|err| error::Error::InvalidRunner(runner.clone(), err)),
+        None => Ok(vec![]),
+    }
+}
+
+/// The `--samply-args` split like a shell would, followed by every `--samply-arg`, the port and
+/// the `--save-only` that `--symbolicate-only` needs.
+fn samply_args(cli: &Config) -> error::Result<Vec<String>> {
+    let mut args = match cli.samply_args.as_ref() {
+        Some(samply_args) => shell_words::split(samply_args)?,
+        None => vec![],
+    };
+    args.extend(cli.samply_arg.iter().cloned());
+    if let Some(port) = cli.port {
+        if args
+            .iter()
+            .any(|a| a == "-P" || a == "--port" || a.starts_with("--port="))
+        {
+            return Err(error::Error::PortGivenTwice);
+        }
+        args.extend(["--port".to_string(), port.to_string()]);
+    }
+    if cli.symbolicate_only && !args.iter().any(|a| a == "-s" || a == "--save-only") {
+        args.push("--save-only".to_string());
+    }
+    Ok(args)
+}
+
+/// The file the recording is saved to, relative to the current directory.
+fn recording_path(cli: &Config) -> error::Result<PathBuf> {
+    if cli.backend == Backend::Perf {
+        return Ok(PathBuf::from(&cli.perf_output));
+    }
+    if let Some(output) = samply_output(&samply_args(cli)?) {
+        return Ok(output);
+    }
+    Ok(PathBuf::from(default_recording_name(cli)))
+}
+
+/// The `-o`/`--output` given in the samply arguments.
+fn samply_output(samply_args: &[String]) -> Option<PathBuf> {
+    let mut args = samply_args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--output" {
+            if let Some(output) = args.next() {
+                return Some(PathBuf::from(output));
+            }
+        } else if let Some(output) = arg.strip_prefix("--output=") {
+            return Some(PathBuf::from(output));
+        }
+    }
+    None
+}
+
+fn default_recording_name(cli: &Config) -> &'static str {
+    if cli.no_compress {
+        "profile.json"
+    } else {
+        "profile.json.gz"
+    }
+}
+
+/// Checks the samply arguments, `execute_plan` creates the `--profile-output-dir`.
+fn check_profile_output_dir(config: &Config) -> error::Result<()> {
+    let samply_args = samply_args(config)?;
+    if config.profile_output_dir.is_some() && samply_output(&samply_args).is_some() {
+        return Err(error::Error::OutputGivenTwice);
+    }
+    Ok(())
+}
+
+/// `cli` with `--profile-output-dir` turned into an `--output` samply argument, naming a file
+/// after the current time and `name`, so that neither runs nor targets overwrite each other.
+fn with_output_file<'a>(cli: &'a Config, name: &str) -> Cow<'a, Config> {
+    let Some(dir) = cli.profile_output_dir.as_ref() else {
+        return Cow::Borrowed(cli);
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let file = dir.join(format!(
+        "{}-{}-{}",
+        timestamp,
+        name,
+        default_recording_name(cli)
+    ));
+    // a run of the same target may have started within the same second
+    let file = std::iter::once(file.clone())
+        .chain((2..).map(|n| numbered_path(&file, n)))
+        .find(|f| !f.exists())
+        .unwrap();
+    let mut cli = cli.clone();
+    cli.samply_arg.push("--output".to_string());
+    cli.samply_arg.push(file.to_string_lossy().into_owned());
+    Cow::Owned(cli)
+}
+
+/// What a profiling run did, `output` is missing if the run failed without saving a recording.
+#[derive(Debug, Serialize)]
+struct RunSummary<'a> {
+    target: &'a str,
+    kind: &'a str,
+    package: Option<&'a str>,
+    artifact: &'a Path,
+    profile: &'a str,
+    exit_code: Option<i32>,
+    output: Option<PathBuf>,
+    build_secs: f64,
+    run_secs: f64,
+}
+
+impl RunSummary<'_> {
+    fn print(&self, format: MessageFormat) -> error::Result<()> {
+        match format {
+            MessageFormat::Json => println!("{}", serde_json::to_string(self)?),
+            MessageFormat::Human | MessageFormat::Names => info!(
+                "profiled '{}' in {:.2}s (built in {:.2}s), {}",
+                self.target,
+                self.run_secs,
+                self.build_secs,
+                match &self.output {
+                    Some(output) => format!("saved to {}", output.display()),
+                    None => "nothing was saved".to_string(),
+                }
+            ),
+        }
+        Ok(())
+    }
+}
+
+/// Empties the environment with `--env-clear`, except for the passed through variables, then
+/// sets the `[env]` variables of the cargo configuration, the `--env-file` variables and the
+/// `--env` variables, later ones taking precedence.
+fn apply_env(cli: &Config, command: &mut Command) -> error::Result<()> {
+    if cli.env_clear {
+        command.env_clear();
+        for key in &cli.env_passthrough {
+            if let Some(value) = env::var_os(key) {
+                command.env(key, value);
+            }
+        }
+    }
+    for (key, value) in cargo_config_env(&env::current_dir()?)? {
+        command.env(key, value);
+    }
+    if let Some(env_file) = &cli.env_file {
+        command.envs(read_env_file(env_file)?);
+    }
+    for value in &cli.env {
+        let (key, value) = parse_env(value)?;
+        command.env(key, value);
+    }
+    Ok(())
+}
+
+fn call(command: &mut Command, timeout: Option<Duration>) -> error::Result<ExitStatus> {
+    match timeout {
+        Some(timeout) => command.call_with_timeout(timeout),
+        None => command.call(),
+    }
+}
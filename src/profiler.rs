@@ -0,0 +1,127 @@
+use std::process::ExitStatus;
+
+use clap::Parser;
+
+use crate::cli::Config;
+use crate::error;
+use crate::plan::{execute_plan, generate_plan, ExecutionPlan};
+
+/// Builder for profiling a target from Rust code, running the same steps as `cargo samply`.
+///
+/// It operates on the cargo project of the current directory.
+///
+/// ```no_run
+/// use cargo_samply::Profiler;
+///
+/// let status = Profiler::new()
+///     .bin("app")
+///     .samply_args("--rate 4000")
+///     .args(["--input", "data.txt"])
+///     .run()?;
+/// assert!(status.success());
+/// # Ok::<(), cargo_samply::error::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Profiler {
+    config: Config,
+    explicit_profile: bool,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    /// Creates a profiler with the defaults of the `cargo samply` command.
+    pub fn new() -> Self {
+        Profiler {
+            config: Config::parse_from(["cargo-samply"]),
+            explicit_profile: false,
+        }
+    }
+
+    /// Binary to run.
+    pub fn bin(mut self, name: impl Into<String>) -> Self {
+        self.config.bin = Some(name.into());
+        self
+    }
+
+    /// Example to run.
+    pub fn example(mut self, name: impl Into<String>) -> Self {
+        self.config.example = Some(name.into());
+        self
+    }
+
+    /// Builds with the given profile instead of `samply`.
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.config.profile = name.into();
+        self.explicit_profile = true;
+        self
+    }
+
+    /// Space or comma separated list of features to enable, adding to earlier calls.
+    pub fn features(mut self, features: impl Into<String>) -> Self {
+        self.config.features.push(features.into());
+        self
+    }
+
+    /// Extra arguments passed to `samply record`, split like a shell would.
+    pub fn samply_args(mut self, args: impl Into<String>) -> Self {
+        self.config.samply_args = Some(args.into());
+        self
+    }
+
+    /// Arguments passed to the profiled binary.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.config.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Runs the binary directly instead of under samply.
+    pub fn no_samply(mut self, no_samply: bool) -> Self {
+        self.config.no_samply = no_samply;
+        self
+    }
+
+    /// Prints the commands [`run`](Self::run) would execute instead of executing them, leaving
+    /// `Cargo.toml` untouched.
+    ///
+    /// ```
+    /// let status = cargo_samply::Profiler::new()
+    ///     .bin("cargo-samply")
+    ///     .dry_run(true)
+    ///     .run()?;
+    /// assert!(status.success());
+    /// # Ok::<(), cargo_samply::error::Error>(())
+    /// ```
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Resolves what would be built and run, without building or writing anything.
+    ///
+    /// ```
+    /// let plan = cargo_samply::Profiler::new().bin("cargo-samply").plan()?;
+    /// assert_eq!(plan.targets.len(), 1);
+    /// assert_eq!(plan.targets[0].bin_opt, "--bin");
+    /// assert_eq!(plan.targets[0].name, "cargo-samply");
+    /// # Ok::<(), cargo_samply::error::Error>(())
+    /// ```
+    pub fn plan(&self) -> error::Result<ExecutionPlan> {
+        generate_plan(self.config.clone(), self.explicit_profile)
+    }
+
+    /// Builds and profiles the target, returning the exit status of the profiled run.
+    ///
+    /// Adds the profile to `Cargo.toml` if it is missing, like the command.
+    pub fn run(&self) -> error::Result<ExitStatus> {
+        execute_plan(&self.plan()?)
+    }
+}
@@ -1,69 +1,961 @@
 use std::{
-    fs::{self, OpenOptions},
-    io::Write,
+    collections::HashSet,
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, BufReader, IsTerminal, Read, Write},
     path::{Path, PathBuf},
-    process::{Command, ExitStatus},
+    process::{Child, Command, ExitStatus, Stdio},
     str::{from_utf8, FromStr},
+    sync::OnceLock,
+    thread,
+    time::{Duration, Instant},
 };
 
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use cargo_metadata::diagnostic::DiagnosticLevel;
+use serde::Serialize;
+
+use crate::cli::{Config, TargetKind};
 use crate::error::{self, IOResultExt};
 
 pub fn locate_project() -> error::Result<PathBuf> {
-    let output = Command::new("cargo")
-        .args(vec![
-            "locate-project",
-            "--workspace",
-            "--message-format",
-            "plain",
-        ])
-        .log()
-        .output()?;
-    if !output.status.success() {
-        return Err(error::Error::CargoLocateProjectFailed);
+    locate(true)
+}
+
+/// Runs `cargo locate-project`, for the workspace root or the package containing the cwd.
+fn locate(workspace: bool) -> error::Result<PathBuf> {
+    let mut args = vec!["locate-project"];
+    if workspace {
+        args.push("--workspace");
+    }
+    args.extend(["--message-format", "plain"]);
+    let output = Command::new(cargo_program()).args(args).log().output()?;
+    if output.status.success() {
+        return Ok(PathBuf::from(from_utf8(&output.stdout)?.trim()));
+    }
+    // cargo also fails on a broken manifest, which is better reported when reading it
+    let cwd = env::current_dir()?;
+    match cwd
+        .ancestors()
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|cargo_toml| cargo_toml.is_file())
+    {
+        Some(cargo_toml) => {
+            debug!("cargo locate-project failed, using {:?}", cargo_toml);
+            Ok(cargo_toml)
+        }
+        None => Err(error::Error::CargoLocateProjectFailed(cwd)),
     }
-    Ok(PathBuf::from(from_utf8(&output.stdout)?.trim()))
 }
 
-const SAMPLY_PROFILE: &str = "
-[profile.samply]
-inherits = \"release\"
-debug = true
-";
+/// The `(major, minor)` version of cargo, if `cargo --version` could be understood.
+pub fn cargo_version() -> Option<(u32, u32)> {
+    let output = Command::new(cargo_program())
+        .arg("--version")
+        .log()
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let mut parts = stdout.split_whitespace().nth(1)?.split('.');
+    Some((parts.next()?.parse().ok()?, parts.next()?.parse().ok()?))
+}
+
+/// The cargo executable, overridable through `CARGO` or `--cargo-path`.
+pub fn cargo_program() -> OsString {
+    env::var_os("CARGO").unwrap_or_else(|| "cargo".into())
+}
 
-pub fn ensure_samply_profile(cargo_toml: &Path) -> error::Result<()> {
-    let cargo_toml_content: String = fs::read_to_string(cargo_toml).path_ctx(cargo_toml)?;
-    let manifest = toml::Table::from_str(&cargo_toml_content)?;
-    let profile_samply = manifest
-        .get("profile")
-        .and_then(|p| p.as_table())
-        .and_then(|p| p.get("samply"));
+/// The samply executable, overridable through `CARGO_SAMPLY_SAMPLY_PATH`.
+pub fn samply_program() -> OsString {
+    env::var_os("CARGO_SAMPLY_SAMPLY_PATH").unwrap_or_else(|| "samply".into())
+}
 
-    if profile_samply.is_none() {
-        let mut f = OpenOptions::new().append(true).open(cargo_toml).unwrap();
-        f.write(SAMPLY_PROFILE.as_bytes()).path_ctx(cargo_toml)?;
-        info!("'samply' profile was added to 'Cargo.toml'");
+/// The `(major, minor, patch)` version of samply, if `samply --version` could be understood.
+///
+/// samply is only asked once, the answer is kept for the rest of the run.
+pub fn samply_version() -> Option<(u32, u32, u32)> {
+    static VERSION: OnceLock<Option<(u32, u32, u32)>> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let output = Command::new(samply_program())
+            .arg("--version")
+            .log()
+            .output()
+            .ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        let mut parts = stdout.split_whitespace().nth(1)?.split(['.', '-']);
+        Some((
+            parts.next()?.parse().ok()?,
+            parts.next()?.parse().ok()?,
+            parts.next()?.parse().ok()?,
+        ))
+    })
+}
+
+/// Whether `samply record` knows the long `option`, going by what `samply record --help` lists.
+/// `None` if samply printed no usable help.
+///
+/// samply is only asked once, the answer is kept for the rest of the run.
+pub fn samply_record_knows(option: &str) -> Option<bool> {
+    static HELP: OnceLock<Option<String>> = OnceLock::new();
+    let help = HELP.get_or_init(|| {
+        let output = Command::new(samply_program())
+            .args(["record", "--help"])
+            .log()
+            .output()
+            .ok()?;
+        let stdout = String::from_utf8(output.stdout).ok()?;
+        // clap's help always carries a usage line, anything else isn't a help text
+        (output.status.success() && stdout.to_lowercase().contains("usage:")).then_some(stdout)
+    });
+    let help = help.as_deref()?;
+    Some(
+        help.split(|c: char| !(c.is_alphanumeric() || c == '-'))
+            .any(|word| word == option),
+    )
+}
+
+/// The perf executable, overridable through `CARGO_SAMPLY_PERF_PATH`.
+pub fn perf_program() -> OsString {
+    env::var_os("CARGO_SAMPLY_PERF_PATH").unwrap_or_else(|| "perf".into())
+}
+
+/// The profiles cargo defines itself, which are never added to the manifest.
+const BUILTIN_PROFILES: [&str; 4] = ["dev", "release", "test", "bench"];
+
+/// Adds `[profile.<profile>]`, inheriting from `inherits` with debug info and the given
+/// opt-level, if it is missing. Returns whether it was added.
+pub fn ensure_profile(
+    cargo_toml: &Path,
+    profile: &str,
+    inherits: &str,
+    opt_level: Option<&str>,
+) -> error::Result<bool> {
+    if BUILTIN_PROFILES.contains(&profile) {
+        return Ok(false);
+    }
+    let mut added = false;
+    update_manifest(cargo_toml, |content| {
+        let manifest = toml::Table::from_str(content)?;
+        let profiles = manifest.get("profile").and_then(|p| p.as_table());
+        if profiles.and_then(|p| p.get(profile)).is_some() {
+            return Ok(None);
+        }
+        // only checked for the table written here, an existing one is left as it is
+        if !BUILTIN_PROFILES.contains(&inherits) && profiles.and_then(|p| p.get(inherits)).is_none()
+        {
+            return Err(error::Error::UnknownBaseProfile(inherits.to_string()));
+        }
+        info!("'{}' profile was added to 'Cargo.toml'", profile);
+        added = true;
+        let mut table = format!("[profile.{}]\ninherits = \"{}\"\n", profile, inherits);
+        match opt_level {
+            Some(level @ ("s" | "z")) => table += &format!("opt-level = \"{}\"\n", level),
+            Some(level) => table += &format!("opt-level = {}\n", level),
+            None => {}
+        }
+        Ok(Some(format!("{}\n{}debug = true\n", content, table)))
+    })?;
+    Ok(added)
+}
+
+/// Changes the parsed `manifest` the way [`ensure_profile`] and, with `repair`, [`repair_profile`]
+/// change the file, so that the profile can be judged before anything is written.
+pub fn plan_profile(manifest: &mut toml::Table, profile: &str, inherits: &str, repair: bool) {
+    let builtin = BUILTIN_PROFILES.contains(&profile);
+    if builtin && !repair {
+        return;
+    }
+    let Some(profiles) = manifest
+        .entry("profile")
+        .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        .as_table_mut()
+    else {
+        return;
+    };
+    let Some(table) = profiles
+        .entry(profile)
+        .or_insert_with(|| {
+            let mut table = toml::Table::new();
+            if !builtin {
+                table.insert("inherits".to_string(), inherits.into());
+                table.insert("debug".to_string(), true.into());
+            }
+            toml::Value::Table(table)
+        })
+        .as_table_mut()
+    else {
+        return;
+    };
+    if repair {
+        table.insert("debug".to_string(), true.into());
+    }
+}
+
+/// Rewrites the manifest with what `update` returns for its content, if anything.
+///
+/// An advisory lock keeps concurrent runs from interleaving, and the new content is renamed over
+/// the manifest so an interrupted write never leaves a partial one behind. The lock is taken on a
+/// file of its own in the target directory, as the manifest is replaced rather than written to.
+fn update_manifest(
+    cargo_toml: &Path,
+    update: impl FnOnce(&str) -> error::Result<Option<String>>,
+) -> error::Result<()> {
+    let lock_path = cargo_toml
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join("target")
+        .join("samply")
+        .join("manifest.lock");
+    if let Some(dir) = lock_path.parent() {
+        fs::create_dir_all(dir).path_ctx(dir)?;
+    }
+    let lock = File::create(&lock_path).path_ctx(&lock_path)?;
+    lock.lock().path_ctx(&lock_path)?;
+    // the rename must replace the file a symlinked manifest points to, not the symlink
+    let cargo_toml = &fs::canonicalize(cargo_toml).path_ctx(cargo_toml)?;
+    let content = fs::read_to_string(cargo_toml).path_ctx(cargo_toml)?;
+    if let Some(updated) = update(&content)? {
+        let tmp = cargo_toml.with_file_name(format!(".Cargo.toml.{}.tmp", std::process::id()));
+        fs::write(&tmp, updated).path_ctx(&tmp)?;
+        let permissions = fs::metadata(cargo_toml).path_ctx(cargo_toml)?.permissions();
+        fs::set_permissions(&tmp, permissions).path_ctx(&tmp)?;
+        fs::rename(&tmp, cargo_toml).path_ctx(cargo_toml)?;
     }
     Ok(())
 }
 
-pub fn guess_bin(cargo_toml: &Path) -> error::Result<String> {
-    let manifest = cargo_toml::Manifest::from_path(cargo_toml)?;
-    let default_run = manifest.package.and_then(|p| p.default_run);
+/// Removes `[profile.<profile>]` from the manifest, and `[profile]` if nothing else is left in it.
+pub fn remove_profile(cargo_toml: &Path, profile: &str) -> error::Result<()> {
+    update_manifest(cargo_toml, |content| {
+        let mut manifest = content.parse::<toml_edit::Document>()?;
+        let removed = match manifest
+            .get_mut("profile")
+            .and_then(|p| p.as_table_like_mut())
+        {
+            Some(profiles) => {
+                let removed = profiles.remove(profile).is_some();
+                if profiles.is_empty() {
+                    manifest.remove("profile");
+                }
+                removed
+            }
+            None => false,
+        };
+        if removed {
+            info!("'{}' profile was removed from 'Cargo.toml'", profile);
+            Ok(Some(manifest.to_string()))
+        } else {
+            info!("'Cargo.toml' has no '{}' profile", profile);
+            Ok(None)
+        }
+    })
+}
+
+/// Makes sure `[profile.<profile>]` builds with debug info by setting `debug = true`.
+pub fn repair_profile(cargo_toml: &Path, profile: &str) -> error::Result<()> {
+    update_manifest(cargo_toml, |content| {
+        let mut manifest = content.parse::<toml_edit::Document>()?;
+        let profiles = manifest
+            .entry("profile")
+            .or_insert_with(|| {
+                let mut profiles = toml_edit::Table::new();
+                profiles.set_implicit(true);
+                toml_edit::Item::Table(profiles)
+            })
+            .as_table_mut()
+            .ok_or_else(|| error::Error::InvalidProfileTable("profile".to_string()))?;
+        let table = profiles
+            .entry(profile)
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| error::Error::InvalidProfileTable(format!("profile.{}", profile)))?;
+
+        let has_debug_info = match table.get("debug").and_then(|d| d.as_value()) {
+            Some(toml_edit::Value::Boolean(b)) => *b.value(),
+            Some(toml_edit::Value::Integer(i)) => *i.value() > 0,
+            Some(toml_edit::Value::String(s)) => s.value() != "none",
+            _ => false,
+        };
+        if has_debug_info {
+            return Ok(None);
+        }
+        table.insert("debug", toml_edit::value(true));
+        info!("'debug = true' was set in '[profile.{}]'", profile);
+        Ok(Some(manifest.to_string()))
+    })
+}
+
+/// Parses a `--profile-package` value, `<spec>=<opt-level>`.
+pub fn parse_profile_package(value: &str) -> error::Result<(String, toml_edit::Value)> {
+    let invalid = || error::Error::InvalidProfilePackage(value.to_string());
+    let (spec, level) = value.split_once('=').ok_or_else(invalid)?;
+    if spec.is_empty() {
+        return Err(invalid());
+    }
+    let level = match level {
+        "0" | "1" | "2" | "3" => toml_edit::Value::from(level.parse::<i64>().expect("a digit")),
+        "s" | "z" => toml_edit::Value::from(level),
+        _ => return Err(invalid()),
+    };
+    Ok((spec.to_string(), level))
+}
+
+/// Sets `opt-level` in `[profile.<profile>.package."<spec>"]` for every override, leaving the
+/// manifest untouched if all of them are set already.
+pub fn set_profile_packages(
+    cargo_toml: &Path,
+    profile: &str,
+    overrides: &[(String, toml_edit::Value)],
+) -> error::Result<()> {
+    update_manifest(cargo_toml, |content| {
+        let mut manifest = content.parse::<toml_edit::Document>()?;
+        let mut changed = false;
+        for (spec, level) in overrides {
+            let mut table = manifest.as_table_mut();
+            for (i, key) in ["profile", profile, "package", spec]
+                .into_iter()
+                .enumerate()
+            {
+                table = table
+                    .entry(key)
+                    .or_insert_with(|| {
+                        let mut table = toml_edit::Table::new();
+                        // only the innermost table needs a header
+                        table.set_implicit(i < 3);
+                        toml_edit::Item::Table(table)
+                    })
+                    .as_table_mut()
+                    .ok_or_else(|| error::Error::InvalidProfileTable(key.to_string()))?;
+            }
+            let current = table.get("opt-level").and_then(|l| l.as_value());
+            let level_repr = level.to_string();
+            if current.map(|l| l.to_string()).as_deref().map(str::trim) != Some(level_repr.trim()) {
+                table.insert("opt-level", toml_edit::value(level.clone()));
+                info!(
+                    "'opt-level = {}' was set in '[profile.{}.package.{}]'",
+                    level,
+                    profile,
+                    toml_edit::Key::new(spec).display_repr()
+                );
+                changed = true;
+            }
+        }
+        Ok(changed.then(|| manifest.to_string()))
+    })
+}
+
+/// Whether `profile` produces debug info, following its `inherits` chain down to the built-in
+/// profiles.
+pub fn profile_has_debug_info(manifest: &toml::Table, profile: &str) -> bool {
+    match profile_setting(manifest, profile, "debug") {
+        Ok(toml::Value::Boolean(b)) => *b,
+        Ok(toml::Value::Integer(i)) => *i > 0,
+        Ok(toml::Value::String(s)) => s != "none",
+        Ok(_) => true,
+        Err(base) => base != "release",
+    }
+}
+
+/// The `strip` setting of `profile` if it removes anything, following its `inherits` chain.
+pub fn profile_strip(manifest: &toml::Table, profile: &str) -> Option<String> {
+    match profile_setting(manifest, profile, "strip") {
+        Ok(toml::Value::Boolean(true)) => Some("true".to_string()),
+        Ok(toml::Value::String(s)) if s != "none" => Some(format!("{:?}", s)),
+        _ => None,
+    }
+}
+
+/// The value of `key` in `profile` or the closest profile it inherits from, or else the
+/// built-in profile the chain ends in, `""` if it can't be told.
+fn profile_setting<'a>(
+    manifest: &'a toml::Table,
+    profile: &str,
+    key: &str,
+) -> Result<&'a toml::Value, &'static str> {
+    let profiles = manifest.get("profile").and_then(|p| p.as_table());
+    let mut current = profile.to_string();
+    // cargo rejects inheritance cycles, the bound only keeps us from looping on one
+    for _ in 0..16 {
+        let table = profiles
+            .and_then(|p| p.get(&current))
+            .and_then(|p| p.as_table());
+        if let Some(value) = table.and_then(|t| t.get(key)) {
+            return Ok(value);
+        }
+        let inherits = table
+            .and_then(|t| t.get("inherits"))
+            .and_then(|i| i.as_str());
+        current = match (current.as_str(), inherits) {
+            ("dev", _) => return Err("dev"),
+            ("release", _) => return Err("release"),
+            (_, Some(parent)) => parent.to_string(),
+            ("test", None) => "dev".to_string(),
+            ("bench", None) => "release".to_string(),
+            // custom profiles must inherit, cargo reports the error
+            (_, None) => return Err(""),
+        };
+    }
+    Err("")
+}
+
+/// Name of the directory below `target` that cargo uses for `profile`.
+pub fn profile_dir(profile: &str) -> &str {
+    match profile {
+        "dev" | "test" => "debug",
+        "bench" => "release",
+        profile => profile,
+    }
+}
+
+/// Reads and completes a manifest, discovering targets from the file layout.
+pub fn read_manifest(cargo_toml: &Path) -> error::Result<cargo_toml::Manifest> {
+    debug!("reading manifest {:?}", cargo_toml);
+    Ok(cargo_toml::Manifest::from_path(cargo_toml)?)
+}
+
+pub fn guess_bin(manifest: &cargo_toml::Manifest) -> error::Result<String> {
+    let default_run = manifest
+        .package
+        .as_ref()
+        .and_then(|p| p.default_run.clone());
+    let bins = manifest.bin.iter().filter_map(|p| p.name.clone()).collect();
+    pick_bin(default_run, bins)
+}
+
+/// Chooses the binary to run like `cargo run` does.
+fn pick_bin(default_run: Option<String>, mut bins: Vec<String>) -> error::Result<String> {
     if let Some(bin) = default_run {
-        Ok(bin)
-    } else if manifest.bin.len() == 1 {
-        return Ok(manifest.bin.first().unwrap().name.clone().unwrap());
-    } else if manifest.bin.is_empty() {
-        return Err(error::Error::NoBinaryFound);
+        if bins.contains(&bin) {
+            Ok(bin)
+        } else {
+            Err(error::Error::DefaultRunNotFound(bin))
+        }
+    } else if bins.len() == 1 {
+        Ok(bins.remove(0))
+    } else if bins.is_empty() {
+        Err(error::Error::NoBinaryFound)
     } else {
-        return Err(error::Error::BinaryToRunNotDetermined);
+        Err(error::Error::BinaryToRunNotDetermined)
+    }
+}
+
+/// How `cargo metadata` is run.
+#[derive(Debug, Clone, Copy)]
+pub struct MetadataOptions {
+    /// Times to try before giving up.
+    pub attempts: u32,
+    pub offline: bool,
+}
+
+impl From<&Config> for MetadataOptions {
+    fn from(cli: &Config) -> Self {
+        MetadataOptions {
+            attempts: cli.metadata_retries,
+            offline: cli.offline,
+        }
+    }
+}
+
+/// Runs `cargo metadata` for the workspace of `cargo_toml`.
+///
+/// Only failures to run cargo are retried, e.g. on a flaky network filesystem, not output that
+/// can't be understood.
+fn workspace_metadata(
+    cargo_toml: &Path,
+    options: MetadataOptions,
+) -> error::Result<cargo_metadata::Metadata> {
+    let mut command = cargo_metadata::MetadataCommand::new();
+    command
+        .cargo_path(cargo_program())
+        .manifest_path(cargo_toml)
+        .no_deps();
+    if options.offline {
+        command.other_options(vec!["--offline".to_string()]);
+    }
+    let mut attempt = 1;
+    loop {
+        debug!("running cargo metadata for {:?}", cargo_toml);
+        match command.exec() {
+            Err(
+                err @ (cargo_metadata::Error::CargoMetadata { .. } | cargo_metadata::Error::Io(_)),
+            ) if attempt < options.attempts => {
+                debug!(
+                    "cargo metadata failed, retrying: {}",
+                    err.to_string().trim_end()
+                );
+                thread::sleep(Duration::from_millis(100 * u64::from(attempt)));
+                attempt += 1;
+            }
+            result => return Ok(result?),
+        }
     }
 }
 
+/// Reads the manifest of the package to take the targets from, and its name if it isn't the root.
+///
+/// That is the root package unless `package` is given. A virtual workspace falls back to the
+/// member containing the current directory, or to its only member.
+pub fn package_manifest(
+    cargo_toml: &Path,
+    package: Option<&str>,
+    metadata: MetadataOptions,
+) -> error::Result<(Option<String>, cargo_toml::Manifest)> {
+    let manifest = read_manifest(cargo_toml)?;
+    if package.is_none() && manifest.package.is_some() {
+        return Ok((None, manifest));
+    }
+    let metadata = workspace_metadata(cargo_toml, metadata)?;
+    let members = metadata.workspace_packages();
+    let available = || {
+        members
+            .iter()
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let member = if let Some(name) = package {
+        members
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| error::Error::NoPackageNamed {
+                name: name.to_string(),
+                suggestion: members
+                    .iter()
+                    .map(|p| (edit_distance(name, &p.name), p.name.as_str()))
+                    .filter(|(distance, _)| *distance <= 2)
+                    .min()
+                    .map(|(_, closest)| format!(" (did you mean '{}'?)", closest))
+                    .unwrap_or_default(),
+                available: available(),
+            })?
+    } else {
+        let current = locate(false)?;
+        match members.iter().find(|p| p.manifest_path == current) {
+            Some(member) => member,
+            None if members.is_empty() => return Err(error::Error::NoBinaryFound),
+            None if members.len() == 1 => &members[0],
+            None => return Err(error::Error::VirtualManifest(available())),
+        }
+    };
+    let manifest = read_manifest(member.manifest_path.as_std_path())?;
+    Ok((Some(member.name.clone()), manifest))
+}
+
+/// The `required-features` of a binary or example.
+pub fn required_features(manifest: &cargo_toml::Manifest, target: &Target) -> Vec<String> {
+    products(manifest, target.bin_opt)
+        .iter()
+        .find(|p| p.name.as_deref() == Some(target.name.as_str()))
+        .map(|p| p.required_features.clone())
+        .unwrap_or_default()
+}
+
+/// Asks on the terminal which binary or example to run, `None` if stdin or stderr isn't one.
+pub fn prompt_target(manifest: &cargo_toml::Manifest) -> error::Result<Option<Target>> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Ok(None);
+    }
+    let mut targets: Vec<Target> = [("--bin", &manifest.bin), ("--example", &manifest.example)]
+        .into_iter()
+        .flat_map(|(bin_opt, products)| {
+            products
+                .iter()
+                .filter_map(move |p| Some(Target::new(bin_opt, p.name.clone()?)))
+        })
+        .collect();
+    for (i, target) in targets.iter().enumerate() {
+        eprintln!("{:>3}) {} {}", i + 1, target.bin_opt, target.name);
+    }
+    loop {
+        eprint!("target to run [1-{}]: ", targets.len());
+        io::stderr().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        match line.trim().parse::<usize>() {
+            Ok(i) if (1..=targets.len()).contains(&i) => {
+                return Ok(Some(targets.swap_remove(i - 1)))
+            }
+            _ => eprintln!("'{}' is not one of the listed numbers", line.trim()),
+        }
+    }
+}
+
+/// A target to build, `bin_opt` being the cargo flag selecting its kind.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub package: Option<String>,
+    pub bin_opt: &'static str,
+    pub name: String,
+}
+
+impl Target {
+    pub fn new(bin_opt: &'static str, name: impl Into<String>) -> Self {
+        Target {
+            package: None,
+            bin_opt,
+            name: name.into(),
+        }
+    }
+}
+
+/// Picks the binary to run for every workspace member, skipping members without one.
+///
+/// All members are resolved from a single `cargo metadata` call.
+pub fn workspace_targets(
+    cargo_toml: &Path,
+    metadata: MetadataOptions,
+) -> error::Result<Vec<Target>> {
+    let metadata = workspace_metadata(cargo_toml, metadata)?;
+    let mut targets = vec![];
+    for package in metadata.workspace_packages() {
+        let bins = package
+            .targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| t.name.clone())
+            .collect();
+        match pick_bin(package.default_run.clone(), bins) {
+            Ok(bin) => targets.push(Target {
+                package: Some(package.name.clone()),
+                ..Target::new("--bin", bin)
+            }),
+            Err(err) => debug!("skipping workspace member '{}': {}", package.name, err),
+        }
+    }
+    Ok(targets)
+}
+
+/// The targets of a package, grouped by kind.
+#[derive(Debug, Default, Serialize)]
+pub struct Targets {
+    pub binaries: Vec<String>,
+    pub examples: Vec<String>,
+    pub benches: Vec<String>,
+    pub tests: Vec<String>,
+}
+
+impl Targets {
+    pub fn from_manifest(manifest: &cargo_toml::Manifest) -> Self {
+        let names = |products: &[cargo_toml::Product]| {
+            products.iter().filter_map(|p| p.name.clone()).collect()
+        };
+        Targets {
+            binaries: names(&manifest.bin),
+            examples: names(&manifest.example),
+            benches: names(&manifest.bench),
+            tests: names(&manifest.test),
+        }
+    }
+
+    /// Drops the targets of every kind not in `kinds`.
+    pub fn retain_kinds(&mut self, kinds: &[TargetKind]) {
+        for (k, names) in [
+            (TargetKind::Bin, &mut self.binaries),
+            (TargetKind::Example, &mut self.examples),
+            (TargetKind::Bench, &mut self.benches),
+            (TargetKind::Test, &mut self.tests),
+        ] {
+            if !kinds.contains(&k) {
+                names.clear();
+            }
+        }
+    }
+
+    /// Prints just the names, one per line.
+    pub fn print_names(&self) {
+        for names in [&self.binaries, &self.examples, &self.benches, &self.tests] {
+            for name in names {
+                println!("{}", name);
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        for (title, names) in [
+            ("Binaries", &self.binaries),
+            ("Examples", &self.examples),
+            ("Benches", &self.benches),
+            ("Tests", &self.tests),
+        ] {
+            if !names.is_empty() {
+                println!("{}:", title);
+                for name in names {
+                    println!("    {}", name);
+                }
+            }
+        }
+    }
+}
+
+/// Finds every target kind, as its cargo flag, for which a target called `name` exists.
+pub fn find_targets_named(
+    manifest: &cargo_toml::Manifest,
+    name: &str,
+) -> error::Result<Vec<Target>> {
+    let mut targets = vec![];
+    for (bin_opt, products) in [
+        ("--bin", &manifest.bin),
+        ("--example", &manifest.example),
+        ("--bench", &manifest.bench),
+        ("--test", &manifest.test),
+    ] {
+        if products.iter().any(|p| p.name.as_deref() == Some(name)) {
+            targets.push(Target::new(bin_opt, name));
+        } else {
+            debug!(
+                "no {} target named '{}'",
+                bin_opt.trim_start_matches('-'),
+                name
+            );
+        }
+    }
+    if targets.is_empty() {
+        return Err(error::Error::NoTargetNamed(name.to_string()));
+    }
+    Ok(targets)
+}
+
+/// The targets of the kind selected by the cargo flag `bin_opt`.
+fn products<'a>(manifest: &'a cargo_toml::Manifest, bin_opt: &str) -> &'a [cargo_toml::Product] {
+    match bin_opt {
+        "--example" => &manifest.example,
+        "--bench" => &manifest.bench,
+        "--test" => &manifest.test,
+        _ => &manifest.bin,
+    }
+}
+
+/// Expands `pattern` to the matching target names if it contains glob characters.
+pub fn expand_target_pattern(
+    manifest: &cargo_toml::Manifest,
+    bin_opt: &'static str,
+    pattern: &str,
+) -> error::Result<Vec<Target>> {
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![Target::new(bin_opt, pattern)]);
+    }
+    let names: Vec<&str> = products(manifest, bin_opt)
+        .iter()
+        .filter_map(|p| p.name.as_deref())
+        .collect();
+    let pattern_chars: Vec<char> = pattern.chars().collect();
+    let targets: Vec<_> = names
+        .iter()
+        .filter(|name| glob_match(&pattern_chars, &name.chars().collect::<Vec<_>>()))
+        .map(|name| Target::new(bin_opt, *name))
+        .collect();
+    if targets.is_empty() {
+        return Err(error::Error::NoTargetMatches {
+            pattern: pattern.to_string(),
+            available: names.join(", "),
+        });
+    }
+    Ok(targets)
+}
+
+/// Matches `name` against a glob pattern supporting `*` and `?`.
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Warnings for the `features` the package doesn't declare, with the declared ones that are
+/// close to each of them.
+///
+/// `dep/feature` forms are left to cargo.
+pub fn unknown_features(manifest: &cargo_toml::Manifest, features: &[String]) -> Vec<String> {
+    let mut declared: Vec<&str> = manifest.features.keys().map(String::as_str).collect();
+    let dep_refs: HashSet<&str> = manifest
+        .features
+        .values()
+        .flatten()
+        .filter_map(|value| value.strip_prefix("dep:"))
+        .collect();
+    // optional dependencies are implicit features unless referenced as `dep:`
+    declared.extend(
+        manifest
+            .dependencies
+            .iter()
+            .filter(|(name, dep)| dep.optional() && !dep_refs.contains(name.as_str()))
+            .map(|(name, _)| name.as_str()),
+    );
+    features
+        .iter()
+        .filter(|f| !f.contains('/') && f.as_str() != "default" && !declared.contains(&f.as_str()))
+        .map(|feature| {
+            let close: Vec<String> = declared
+                .iter()
+                .filter(|d| edit_distance(feature, d) <= 2)
+                .map(|d| format!("'{}'", d))
+                .collect();
+            if close.is_empty() {
+                format!("the package declares no feature '{}'", feature)
+            } else {
+                format!(
+                    "the package declares no feature '{}', did you mean {}?",
+                    feature,
+                    close.join(" or ")
+                )
+            }
+        })
+        .collect()
+}
+
+/// The Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Splits the `--features` values on whitespace and commas like cargo does, dropping duplicates.
+pub fn normalize_features(values: &[String]) -> Vec<String> {
+    let mut features: Vec<String> = vec![];
+    for feature in values
+        .iter()
+        .flat_map(|v| v.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|f| !f.is_empty())
+    {
+        if !features.iter().any(|f| f == feature) {
+            features.push(feature.to_string());
+        }
+    }
+    features
+}
+
+/// Builds a stable fingerprint of the feature selection used for a build.
+pub fn features_fingerprint(features: &[String], no_default_features: bool) -> String {
+    let mut features = features.to_vec();
+    features.sort_unstable();
+    format!(
+        "features={}\nno-default-features={}\n",
+        features.join(","),
+        no_default_features
+    )
+}
+
+/// Path of the fingerprint file remembering the features a target was last built with.
+pub fn fingerprint_path(root: &Path, kind: &str, name: &str) -> PathBuf {
+    root.join("target")
+        .join("samply")
+        .join(format!("{}-{}.fingerprint", kind, name))
+}
+
+/// Path of the file remembering where the last recording in the workspace at `root` was saved.
+pub fn last_profile_path(root: &Path) -> PathBuf {
+    root.join("target").join("samply").join("last-profile")
+}
+
+/// Returns `true` if a fingerprint was recorded before and differs from `fingerprint`.
+pub fn features_fingerprint_changed(path: &Path, fingerprint: &str) -> bool {
+    fs::read_to_string(path).is_ok_and(|previous| previous != fingerprint)
+}
+
+pub fn write_features_fingerprint(path: &Path, fingerprint: &str) -> error::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).path_ctx(dir)?;
+    }
+    fs::write(path, fingerprint).path_ctx(path)
+}
+
+/// Runs a `cargo build` emitting JSON messages, returning the executable cargo reports for
+/// `target` if any.
+///
+/// The executable path is authoritative, e.g. for examples in subdirectories, the caller falls
+/// back to the conventional path if cargo reports none.
+pub fn build_target(
+    command: &mut Command,
+    target: &Target,
+    cli: &Config,
+) -> error::Result<(ExitStatus, Option<PathBuf>)> {
+    // added here rather than to the command users get to see, which should work when pasted
+    if cli.quiet {
+        // the diagnostics come back as JSON, and only the errors are printed below
+        let format = if cli.color.enabled() {
+            "json-diagnostic-rendered-ansi"
+        } else {
+            "json"
+        };
+        command.args(["--quiet", "--message-format", format]);
+    } else {
+        command.args(["--message-format", "json-render-diagnostics"]);
+    }
+    command.log();
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut executable = None;
+    for message in cargo_metadata::Message::parse_stream(BufReader::new(stdout)) {
+        match message? {
+            cargo_metadata::Message::CompilerArtifact(artifact)
+                if is_target_artifact(&artifact, target) =>
+            {
+                if let Some(path) = artifact.executable {
+                    executable = Some(path.into_std_path_buf());
+                }
+            }
+            cargo_metadata::Message::CompilerMessage(message)
+                if matches!(
+                    message.message.level,
+                    DiagnosticLevel::Error | DiagnosticLevel::Ice
+                ) =>
+            {
+                if let Some(rendered) = message.message.rendered {
+                    eprint!("{}", rendered);
+                }
+            }
+            cargo_metadata::Message::TextLine(line) => println!("{}", line),
+            _ => {}
+        }
+    }
+    Ok((child.wait()?, executable))
+}
+
+/// Whether `artifact` is the one built for `target`, not a same-named target of another kind,
+/// such as the binary `cargo build --test <name>` builds alongside a test of the same name.
+fn is_target_artifact(artifact: &cargo_metadata::Artifact, target: &Target) -> bool {
+    let kind = target.bin_opt.trim_start_matches('-');
+    artifact.target.name == target.name
+        && artifact.target.kind.iter().any(|k| k == kind)
+        // only tests and benches are compiled as test harnesses
+        && artifact.profile.test == matches!(kind, "test" | "bench")
+}
+
 /// Extension trait for `Command` that add a `call` method which logs the command in debug mode.
 pub trait CommandExt {
     fn call(&mut self) -> error::Result<ExitStatus>;
+    /// Like `call`, but terminates the process and everything it started once `timeout` has
+    /// elapsed.
+    fn call_with_timeout(&mut self, timeout: Duration) -> error::Result<ExitStatus>;
+    /// Like `call`, but also returns a copy of everything the process wrote to stderr, which is
+    /// passed on to our stderr meanwhile. The process sees a pipe instead of a terminal then.
+    fn call_capturing_stderr(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> error::Result<(ExitStatus, String)>;
     fn log(&mut self) -> &mut Command;
+    /// The command line as it could be typed into a shell, prefixed by the variables it sets.
+    fn display(&self) -> String;
 }
 
 impl CommandExt for Command {
@@ -71,6 +963,46 @@ impl CommandExt for Command {
         self.log();
         Ok(self.spawn()?.wait()?)
     }
+    fn call_with_timeout(&mut self, timeout: Duration) -> error::Result<ExitStatus> {
+        self.log();
+        own_process_group(self);
+        let mut child = self.spawn()?;
+        wait_with_timeout(&mut child, timeout)
+    }
+    fn call_capturing_stderr(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> error::Result<(ExitStatus, String)> {
+        self.log();
+        if timeout.is_some() {
+            own_process_group(self);
+        }
+        let mut child = self.stderr(Stdio::piped()).spawn()?;
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        // passed on as it arrives rather than by line, so that prompts and progress show up
+        let reader = thread::spawn(move || {
+            let mut captured = vec![];
+            let mut buf = [0; 8192];
+            loop {
+                match stderr.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let mut out = io::stderr().lock();
+                        let _ = out.write_all(&buf[..n]).and_then(|_| out.flush());
+                        captured.extend_from_slice(&buf[..n]);
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                    Err(_) => break,
+                }
+            }
+            String::from_utf8_lossy(&captured).into_owned()
+        });
+        let status = match timeout {
+            Some(timeout) => wait_with_timeout(&mut child, timeout)?,
+            None => child.wait()?,
+        };
+        Ok((status, reader.join().unwrap_or_default()))
+    }
     fn log(&mut self) -> &mut Command {
         debug!(
             "running {:?} with args: {:?}",
@@ -79,4 +1011,347 @@ impl CommandExt for Command {
         );
         self
     }
+    fn display(&self) -> String {
+        let envs = self.get_envs().filter_map(|(key, value)| {
+            Some(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                shell_words::quote(&value?.to_string_lossy())
+            ))
+        });
+        let words = std::iter::once(self.get_program())
+            .chain(self.get_args())
+            .map(|word| shell_words::quote(&word.to_string_lossy()).into_owned());
+        envs.chain(words).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Waits for a child started in its own process group, interrupting the group once `timeout` has
+/// elapsed. Ctrl+C reaches only us meanwhile, it is passed on to the group while interrupts are
+/// ignored.
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> error::Result<ExitStatus> {
+    forward_interrupts_to(child.id());
+    let status = wait_or_terminate(child, timeout);
+    forward_interrupts_to(0);
+    status
+}
+
+fn wait_or_terminate(child: &mut Child, timeout: Duration) -> error::Result<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    debug!("terminating process {} after {:?}", child.id(), timeout);
+    terminate(child)?;
+    Ok(child.wait()?)
+}
+
+/// The `[env]` variables of the cargo configuration files that apply in `dir`, as `cargo run`
+/// would set them.
+///
+/// Closer files override farther ones, and variables already in our environment are only
+/// replaced if their entry sets `force`.
+pub fn cargo_config_env(dir: &Path) -> error::Result<Vec<(String, OsString)>> {
+    let mut vars: Vec<(String, OsString)> = vec![];
+    for file in cargo_config_files(dir).iter().rev() {
+        let content = fs::read_to_string(file).path_ctx(file)?;
+        let config = toml::Table::from_str(&content)?;
+        let Some(table) = config.get("env").and_then(|e| e.as_table()) else {
+            continue;
+        };
+        // relative values are relative to the directory containing `.cargo`
+        let root = file.parent().and_then(Path::parent).unwrap_or(dir);
+        for (key, entry) in table {
+            let (value, force, relative) = match entry {
+                toml::Value::String(value) => (value.as_str(), false, false),
+                toml::Value::Table(entry) => {
+                    let flag = |name| entry.get(name).and_then(|f| f.as_bool()) == Some(true);
+                    match entry.get("value").and_then(|v| v.as_str()) {
+                        Some(value) => (value, flag("force"), flag("relative")),
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+            if !force && env::var_os(key).is_some() {
+                continue;
+            }
+            let value = if relative {
+                root.join(value).into_os_string()
+            } else {
+                value.into()
+            };
+            vars.retain(|(k, _)| k != key);
+            vars.push((key.clone(), value));
+        }
+    }
+    Ok(vars)
+}
+
+/// The cargo configuration files that apply in `dir`, the closest first.
+fn cargo_config_files(dir: &Path) -> Vec<PathBuf> {
+    let cargo_home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".cargo")));
+    let mut seen = HashSet::new();
+    dir.ancestors()
+        .map(|d| d.join(".cargo"))
+        .chain(cargo_home)
+        .filter_map(|d| {
+            [d.join("config.toml"), d.join("config")]
+                .into_iter()
+                .find(|f| f.is_file())
+        })
+        // the cargo home is often one of the ancestors' `.cargo` too, read it only once
+        .filter(|f| seen.insert(fs::canonicalize(f).unwrap_or_else(|_| f.clone())))
+        .collect()
+}
+
+/// Where the cargo configuration for `dir` sets rustflags: the names of the `[target.<name>]`
+/// tables that do, a triple or a `cfg(..)` expression, and whether `build.rustflags` does.
+pub fn configured_rustflags(dir: &Path) -> error::Result<(Vec<String>, bool)> {
+    let mut targets = vec![];
+    let mut build = env::var_os("CARGO_BUILD_RUSTFLAGS").is_some();
+    for file in cargo_config_files(dir) {
+        let content = fs::read_to_string(&file).path_ctx(&file)?;
+        let config = toml::Table::from_str(&content)?;
+        let sets_rustflags = |table: &toml::Value| table.get("rustflags").is_some();
+        build |= config.get("build").is_some_and(sets_rustflags);
+        if let Some(tables) = config.get("target").and_then(|t| t.as_table()) {
+            targets.extend(
+                tables
+                    .iter()
+                    .filter(|(_, table)| sets_rustflags(table))
+                    .map(|(name, _)| name.clone()),
+            );
+        }
+    }
+    Ok((targets, build))
+}
+
+/// The triple of the host, as `rustc -vV` reports it.
+pub fn host_triple() -> Option<String> {
+    let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+    let output = Command::new(rustc).arg("-vV").log().output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    stdout
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .map(str::to_string)
+}
+
+/// Reads the `KEY=VALUE` lines of a dotenv file, skipping blank lines and `#` comments.
+///
+/// Values may be double quoted, with `\n`, `\"` and `\\` escapes, or single quoted, taken as is.
+/// Unquoted values end at a ` #` comment. An `export ` prefix is ignored.
+pub fn read_env_file(path: &Path) -> error::Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path).path_ctx(path)?;
+    let mut vars = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let invalid = |reason| error::Error::InvalidEnvFile {
+            path: path.to_path_buf(),
+            line: index + 1,
+            reason,
+        };
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected `KEY=VALUE`"))?;
+        let key = key.trim();
+        if !is_env_key(key) {
+            return Err(invalid("invalid variable name"));
+        }
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some(c @ ('"' | '\\')) => unescaped.push(c),
+                        _ => return Err(invalid("invalid escape sequence")),
+                    },
+                    Some(c) => unescaped.push(c),
+                    None => return Err(invalid("unterminated double quote")),
+                }
+            }
+            let rest = chars.as_str().trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(invalid("unexpected characters after the closing quote"));
+            }
+            unescaped
+        } else if let Some(quoted) = value.strip_prefix('\'') {
+            let (literal, rest) = quoted
+                .split_once('\'')
+                .ok_or_else(|| invalid("unterminated single quote"))?;
+            let rest = rest.trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(invalid("unexpected characters after the closing quote"));
+            }
+            literal.to_string()
+        } else {
+            match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            }
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// Parses an `--env` value, `KEY=VALUE`.
+pub fn parse_env(value: &str) -> error::Result<(String, String)> {
+    match value.split_once('=') {
+        Some((key, value)) if is_env_key(key) => Ok((key.to_string(), value.to_string())),
+        _ => Err(error::Error::InvalidEnv(value.to_string())),
+    }
+}
+
+fn is_env_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether the built artifact carries debug info, `None` if that can't be told.
+///
+/// Mach-O binaries are not judged, their DWARF usually stays in the object files or a `.dSYM`.
+pub fn artifact_has_debug_info(path: &Path) -> error::Result<Option<bool>> {
+    use object::Object;
+
+    // reads only the parts that are looked at, binaries with debug info get large
+    let cache = object::ReadCache::new(File::open(path).path_ctx(path)?);
+    let Ok(file) = object::File::parse(&cache) else {
+        return Ok(None);
+    };
+    Ok(match file.format() {
+        object::BinaryFormat::Elf => Some(file.section_by_name(".debug_info").is_some()),
+        object::BinaryFormat::Pe => {
+            // cargo names the PDB after the crate, with underscores instead of dashes
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let pdb = path.with_file_name(format!("{}.pdb", stem.replace('-', "_")));
+            Some(pdb.exists())
+        }
+        _ => None,
+    })
+}
+
+/// The line of samply's output telling that its server port is taken, if there is one.
+pub fn samply_port_in_use(stderr: &str) -> Option<&str> {
+    stderr
+        .lines()
+        .find(|line| line.contains("Address already in use") || line.contains("AddrInUse"))
+        .map(str::trim)
+}
+
+/// Explains the samply failures whose message alone doesn't tell the user what to do.
+pub fn samply_failure_hint(stderr: &str) -> Option<&'static str> {
+    if stderr.contains("Could not obtain the root task") {
+        Some(
+            "samply could not attach to the program; it may have exited before the recording \
+             started, or failed to start at all, e.g. because a dynamic library was not found",
+        )
+    } else if stderr.contains("unexpected argument '--save-only'") {
+        Some(
+            "this samply can't save a profile without opening it, which `--symbolicate-only` \
+             needs; update it with `cargo install --locked samply`",
+        )
+    } else {
+        None
+    }
+}
+
+/// The process group Ctrl+C is passed on to while interrupts are ignored, 0 for none.
+#[cfg(unix)]
+static INTERRUPT_GROUP: AtomicI32 = AtomicI32::new(0);
+
+/// Ignores Ctrl+C in this process until dropped, like `cargo run` does while the program runs.
+///
+/// In samply's server mode Ctrl+C is how the user stops samply, which must not stop us before the
+/// `--after-run` hooks ran and the injected profile got reverted. A handler rather than `SIG_IGN`
+/// is installed, because children inherit ignored signals but not handlers.
+#[must_use]
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    let group = INTERRUPT_GROUP.load(Ordering::Relaxed);
+    if group != 0 {
+        // SAFETY: `kill` is async-signal-safe.
+        unsafe { libc::kill(-group, libc::SIGINT) };
+    }
+}
+
+#[cfg(unix)]
+pub fn ignore_interrupts() -> InterruptGuard {
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // SAFETY: the handler only touches an atomic and calls `kill`, both async-signal-safe.
+    let previous = unsafe { libc::signal(libc::SIGINT, handler) };
+    InterruptGuard { previous }
+}
+
+#[cfg(not(unix))]
+pub fn ignore_interrupts() -> InterruptGuard {
+    InterruptGuard {}
+}
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: restores the disposition `ignore_interrupts` replaced.
+        unsafe { libc::signal(libc::SIGINT, self.previous) };
+    }
+}
+
+#[cfg(unix)]
+fn forward_interrupts_to(group: u32) {
+    INTERRUPT_GROUP.store(group as i32, Ordering::Relaxed);
+}
+
+#[cfg(not(unix))]
+fn forward_interrupts_to(_group: u32) {}
+
+/// Starts the command in a process group of its own, led by the process, so that `terminate`
+/// reaches the programs it launches too.
+#[cfg(unix)]
+fn own_process_group(command: &mut Command) {
+    use std::os::unix::process::CommandExt as _;
+
+    command.process_group(0);
+}
+
+#[cfg(not(unix))]
+fn own_process_group(_command: &mut Command) {}
+
+/// Asks the child to stop. On Unix this sends `SIGINT` to its process group, as Ctrl+C would,
+/// because samply ignores `SIGINT` while the program it launched runs; the program exiting is
+/// what makes samply save the profile.
+#[cfg(unix)]
+fn terminate(child: &mut Child) -> error::Result<()> {
+    // SAFETY: `kill` has no memory safety preconditions, the child is unreaped and leads the
+    // process group `own_process_group` put it in.
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGINT) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) -> error::Result<()> {
+    Ok(child.kill()?)
 }
@@ -0,0 +1,8 @@
+use std::fs;
+
+fn main() {
+    match fs::read_to_string("marker") {
+        Ok(marker) => println!("marker: {}", marker.trim()),
+        Err(_) => println!("no marker"),
+    }
+}
@@ -0,0 +1,6 @@
+fn main() {
+    for key in ["GREETING", "DATA", "KEPT", "FORCED"] {
+        let key = format!("CARGO_SAMPLY_TEST_{key}");
+        println!("{key}={}", std::env::var(&key).unwrap_or_default());
+    }
+}
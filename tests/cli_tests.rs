@@ -6,14 +6,20 @@ fn trycmd() {
     let cargo_bins = which_re(Regex::new("^cargo-.*").unwrap())
         .unwrap()
         .collect::<Vec<_>>();
+    // `tests/bin/samply` stands in for samply, see the variables it reads
+    let fake_bin = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/bin");
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    let path = std::env::join_paths(std::iter::once(fake_bin).chain(std::env::split_paths(&path)))
+        .unwrap();
     let test = trycmd::TestCases::new();
+    test.env("PATH", path.to_string_lossy());
     let mut t = test
         // .case("README.md")
         .case("tests/*.trycmd")
         .register_bin("cargo", trycmd::schema::Bin::Path(which("cargo").unwrap()));
 
     for pth in cargo_bins.iter().filter(|pth| {
-        !pth.extension().is_some_and(|pth| pth == "exe")
+        pth.extension().is_none_or(|pth| pth != "exe")
             && pth.file_name().is_some_and(|p| p != "cargo-samply")
     }) {
         println!("{}", pth.file_name().unwrap().to_string_lossy());
@@ -0,0 +1,7 @@
+use std::{thread, time::Duration};
+
+fn main() {
+    println!("started");
+    thread::sleep(Duration::from_secs(60));
+    println!("finished");
+}
@@ -0,0 +1,5 @@
+fn main() {
+    let mut vars: Vec<String> = std::env::vars().map(|(k, v)| format!("{k}={v}")).collect();
+    vars.sort();
+    println!("environment: {:?}", vars);
+}
@@ -0,0 +1,8 @@
+fn main() {
+    for key in ["GREETING", "QUOTED", "LITERAL", "EXPORTED", "OVERRIDDEN"] {
+        match std::env::var(key) {
+            Ok(value) => println!("{key}=<{value}>"),
+            Err(_) => println!("{key} is not set"),
+        }
+    }
+}
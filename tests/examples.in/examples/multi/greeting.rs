@@ -0,0 +1 @@
+pub const GREETING: &str = "Hello from a multi-file example!";
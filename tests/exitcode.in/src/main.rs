@@ -0,0 +1,6 @@
+use std::process::exit;
+
+fn main() {
+    println!("exiting with 3");
+    exit(3);
+}
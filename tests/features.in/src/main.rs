@@ -0,0 +1,7 @@
+fn main() {
+    if cfg!(feature = "fast") {
+        println!("Hello, fast world!");
+    } else {
+        println!("Hello, world!");
+    }
+}
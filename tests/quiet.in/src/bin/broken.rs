@@ -0,0 +1,4 @@
+fn main() {
+    let number: u32 = "not a number";
+    println!("{}", number);
+}
@@ -0,0 +1,6 @@
+fn main() {
+    println!("running");
+    if std::env::args().any(|arg| arg == "fail") {
+        std::process::exit(3);
+    }
+}
@@ -0,0 +1,7 @@
+use std::io::stdin;
+
+fn main() {
+    let mut line = String::new();
+    stdin().read_line(&mut line).unwrap();
+    println!("read {:?}", line.trim_end());
+}
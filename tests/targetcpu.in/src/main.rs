@@ -0,0 +1,6 @@
+fn main() {
+    println!("Hello, world!");
+    if cfg!(from_config) {
+        println!("built with the configured rustflags");
+    }
+}
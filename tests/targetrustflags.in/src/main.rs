@@ -0,0 +1,6 @@
+fn main() {
+    println!("Hello, world!");
+    if cfg!(from_target) {
+        println!("built with the rustflags of the target table");
+    }
+}
@@ -0,0 +1,8 @@
+#[test]
+fn alpha() {}
+
+#[test]
+fn alpha_slow() {}
+
+#[test]
+fn beta() {}